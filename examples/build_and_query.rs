@@ -1,7 +1,7 @@
 use kira_cdh_compat_lsh::{
     kmv::KmvSketch,
     lsh::{LshIndex, LshParams},
-    sketch::jaccard_from_signatures,
+    sketch::jaccard_kmv,
 };
 
 fn main() {
@@ -32,6 +32,6 @@ fn main() {
     let cands = index.query_candidates(&sig_a, 1);
     println!("Candidates for A: {:?}", cands);
 
-    let j = jaccard_from_signatures(&sig_a, &sig_b);
+    let j = jaccard_kmv(&sig_a, &sig_b, k);
     println!("Estimated Jaccard(A,B): {:.3}", j);
 }
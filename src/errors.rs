@@ -1,16 +1,140 @@
-use thiserror::Error;
+// Display and Error are implemented by hand (rather than derived with
+// thiserror) so the error types are also available under `no_std`.
+use core::fmt;
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum LshError {
-    #[error(
-        "invalid LSH params: bands * rows must equal signature length (got bands={bands}, rows={rows}, sig_len={sig_len})"
-    )]
     InvalidParams {
         bands: usize,
         rows: usize,
         sig_len: usize,
     },
 
-    #[error("signature length {sig_len} is smaller than bands*rows={need}")]
-    ShortSignature { sig_len: usize, need: usize },
+    ShortSignature {
+        sig_len: usize,
+        need: usize,
+    },
+
+    AlreadyPresent,
+
+    IncompatibleIndex,
+
+    LengthMismatch {
+        sig_len: usize,
+        need: usize,
+    },
+}
+
+impl fmt::Display for LshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LshError::InvalidParams {
+                bands,
+                rows,
+                sig_len,
+            } => write!(
+                f,
+                "invalid LSH params: bands * rows must equal signature length (got bands={bands}, rows={rows}, sig_len={sig_len})"
+            ),
+            LshError::ShortSignature { sig_len, need } => {
+                write!(
+                    f,
+                    "signature length {sig_len} is smaller than bands*rows={need}"
+                )
+            }
+            LshError::AlreadyPresent => f.write_str("id is already present in the index"),
+            LshError::IncompatibleIndex => {
+                f.write_str("cannot merge indexes with different params, seed or band hasher")
+            }
+            LshError::LengthMismatch { sig_len, need } => write!(
+                f,
+                "signature length {sig_len} does not equal bands*rows={need} (strict index)"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for LshError {}
+
+#[derive(Debug)]
+pub enum SketchError {
+    ZeroK,
+
+    KMismatch { left: usize, right: usize },
+
+    LengthMismatch { left: usize, right: usize },
+
+    IncompatibleSeeds,
+
+    InvalidSignature(&'static str),
+
+    DeserializeFailed(&'static str),
+}
+
+impl fmt::Display for SketchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SketchError::ZeroK => f.write_str("sketch size k must be non-zero"),
+            SketchError::KMismatch { left, right } => write!(
+                f,
+                "cannot merge sketches with different k (left={left}, right={right})"
+            ),
+            SketchError::LengthMismatch { left, right } => {
+                write!(f, "signature lengths differ (left={left}, right={right})")
+            }
+            SketchError::IncompatibleSeeds => {
+                f.write_str("cannot merge MinHash signatures built from different seeds")
+            }
+            SketchError::InvalidSignature(why) => write!(f, "invalid signature: {why}"),
+            SketchError::DeserializeFailed(why) => {
+                write!(f, "failed to deserialize sketch: {why}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for SketchError {}
+
+/// Any error of this crate, so downstream code can use one `Result` type
+/// and `?` across index and sketch calls. Displays as the wrapped error.
+#[derive(Debug)]
+pub enum Error {
+    Lsh(LshError),
+
+    Params(crate::lsh::LshParamsError),
+
+    Sketch(SketchError),
+}
+
+/// `Result` with the crate-wide [`Error`].
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lsh(e) => e.fmt(f),
+            Error::Params(e) => e.fmt(f),
+            Error::Sketch(e) => e.fmt(f),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+impl From<LshError> for Error {
+    fn from(e: LshError) -> Self {
+        Error::Lsh(e)
+    }
+}
+
+impl From<crate::lsh::LshParamsError> for Error {
+    fn from(e: crate::lsh::LshParamsError) -> Self {
+        Error::Params(e)
+    }
+}
+
+impl From<SketchError> for Error {
+    fn from(e: SketchError) -> Self {
+        Error::Sketch(e)
+    }
 }
@@ -1,45 +1,406 @@
 //! KMV (k-minimum values, aka bottom-k) sketch over a single hash stream.
 //! Typically faster than classical MinHash as it uses a single hash per element.
 
-use std::cmp::Reverse;
-use std::collections::BinaryHeap;
+use crate::errors::SketchError;
+use crate::util::splitmix64;
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use hashbrown::HashSet;
+use rustc_hash::FxBuildHasher;
 
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Format tag written as the first byte of `KmvSketch::serialize` output.
+const SERIAL_VERSION: u8 = 1;
+
+#[derive(Clone)]
 pub struct KmvSketch {
     k: usize,
-    heap: BinaryHeap<Reverse<u64>>, // max-at-top via Reverse to keep smallest k
+    heap: BinaryHeap<u64>, // max-heap: top is the largest of the kept minima
+    // The values in `heap`, for O(1) duplicate checks in `update`.
+    members: HashSet<u64, FxBuildHasher>,
+    updates: u64,
 }
 
 impl KmvSketch {
     /// Create a KMV sketch keeping k smallest values.
+    ///
+    /// # Panics
+    /// In debug builds, if `k == 0`; a zero-size sketch silently yields empty
+    /// signatures. Prefer [`try_new`](Self::try_new) for user-supplied `k`.
     pub fn new(k: usize) -> Self {
+        debug_assert!(k > 0, "KmvSketch k must be non-zero");
         Self {
             k,
             heap: BinaryHeap::with_capacity(k + 1),
+            members: HashSet::with_capacity_and_hasher(k, FxBuildHasher),
+            updates: 0,
         }
     }
 
-    /// Update with a pre-hashed k-mer value (u64).
+    /// Fallible constructor: `SketchError::ZeroK` if `k == 0`.
+    pub fn try_new(k: usize) -> Result<Self, SketchError> {
+        if k == 0 {
+            return Err(SketchError::ZeroK);
+        }
+        Ok(Self::new(k))
+    }
+
+    /// Update with a pre-hashed k-mer value (u64). Repeated values are ignored.
     #[inline]
     pub fn update(&mut self, h: u64) {
+        self.updates += 1;
         if self.heap.len() < self.k {
-            self.heap.push(Reverse(h));
-        } else if let Some(&Reverse(top)) = self.heap.peek() {
-            // "top" is currently the largest among kept minima (due to Reverse)
-            if h < top {
+            if self.members.insert(h) {
+                self.heap.push(h);
+            }
+        } else if let Some(&top) = self.heap.peek() {
+            // "top" is the largest among kept minima; only smaller values enter.
+            if h < top && self.members.insert(h) {
+                self.members.remove(&top);
                 self.heap.pop();
-                self.heap.push(Reverse(h));
+                self.heap.push(h);
+            }
+        }
+    }
+
+    /// Update with an element seen `count` times, as `count` distinct draws:
+    /// `h` itself plus `count - 1` sub-hashes `splitmix64(h ^ i)`, so
+    /// `count == 1` is exactly `update(h)` and `count == 0` does nothing.
+    ///
+    /// Sub-hashes depend only on `h` and `i`, so two sketches expand a shared
+    /// element identically and `jaccard_kmv` between them estimates the
+    /// weighted Jaccard `sum(min(m_a, m_b)) / sum(max(m_a, m_b))` over
+    /// multiplicities instead of the set Jaccard. Costs `count` updates and
+    /// adds `count` to `num_updates`.
+    pub fn update_weighted(&mut self, h: u64, count: u32) {
+        if count == 0 {
+            return;
+        }
+        self.update(h);
+        for i in 1..u64::from(count) {
+            self.update(splitmix64(h ^ i));
+        }
+    }
+
+    /// Number of distinct minima currently held (at most `k`).
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// True once the sketch holds `k` values, i.e. its estimate uses a full bottom-k.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.heap.len() == self.k
+    }
+
+    /// Largest kept minimum (the heap top), or `None` if empty. Once the
+    /// sketch `is_full`, `update` ignores every `h >= current_max()`, so
+    /// callers can pre-filter hashes against it without calling `update`.
+    #[inline]
+    pub fn current_max(&self) -> Option<u64> {
+        self.heap.peek().copied()
+    }
+
+    /// Kept value at fraction `p` of the sorted contents (`0.0` is the
+    /// smallest, `1.0` the same as `current_max`), or `None` if empty. Sorts
+    /// a copy of the kept values, so it costs O(k log k).
+    ///
+    /// # Panics
+    /// If `p` is not in `[0.0, 1.0]`.
+    pub fn quantile(&self, p: f64) -> Option<u64> {
+        assert!((0.0..=1.0).contains(&p), "quantile must be in [0, 1]");
+        if self.heap.is_empty() {
+            return None;
+        }
+        let mut values = self.heap.clone().into_vec();
+        values.sort_unstable();
+        let rank = (p * (values.len() - 1) as f64) as usize;
+        Some(values[rank])
+    }
+
+    /// Drop all kept values while retaining the heap's capacity, so one
+    /// sketch can be reused across many sequences.
+    pub fn reset(&mut self) {
+        self.heap.clear();
+        self.members.clear();
+        self.updates = 0;
+    }
+
+    /// Number of `update` calls since construction or `reset`, duplicates
+    /// included; this is not a distinct count (`len` is the distinct-minima
+    /// proxy). Merging adds the other sketch's count; sketches rebuilt by
+    /// `from_signature` or `deserialize` start at zero.
+    #[inline]
+    pub fn num_updates(&self) -> u64 {
+        self.updates
+    }
+
+    /// Merge `other` into `self`, keeping the k smallest distinct values of both.
+    /// Both sketches must have been created with the same `k`.
+    pub fn merge(&mut self, other: &KmvSketch) -> Result<(), SketchError> {
+        self.check_k(other)?;
+        self.updates += other.updates;
+        self.absorb(other.heap.iter().copied());
+        Ok(())
+    }
+
+    /// Consuming variant of [`merge`](Self::merge): moves this sketch's values
+    /// into `target`, e.g. to fold a `Vec<KmvSketch>` without cloning.
+    pub fn merge_into(self, target: &mut KmvSketch) -> Result<(), SketchError> {
+        target.check_k(&self)?;
+        target.updates += self.updates;
+        target.absorb(self.heap.into_iter());
+        Ok(())
+    }
+
+    fn check_k(&self, other: &KmvSketch) -> Result<(), SketchError> {
+        if self.k != other.k {
+            return Err(SketchError::KMismatch {
+                left: self.k,
+                right: other.k,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rebuild a sketch from a finished signature so `update` can resume,
+    /// e.g. to extend a sketch with k-mers from an adjacent region.
+    ///
+    /// `sig` must be strictly ascending (distinct minima, as `finish` returns)
+    /// and hold at most `k` values.
+    pub fn from_signature(k: usize, sig: &[u64]) -> Result<KmvSketch, SketchError> {
+        if k == 0 {
+            return Err(SketchError::ZeroK);
+        }
+        if sig.len() > k {
+            return Err(SketchError::InvalidSignature("more values than k"));
+        }
+        if sig.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(SketchError::InvalidSignature(
+                "values not strictly ascending",
+            ));
+        }
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        heap.extend(sig.iter().copied());
+        let mut members = HashSet::with_capacity_and_hasher(k, FxBuildHasher);
+        members.extend(sig.iter().copied());
+        Ok(KmvSketch {
+            k,
+            heap,
+            members,
+            updates: 0,
+        })
+    }
+
+    /// Encode the sketch as bytes: a one-byte format version, then `k`, the
+    /// number of kept values and the values in ascending order, all as
+    /// little-endian u64.
+    pub fn serialize(&self) -> Vec<u8> {
+        let values = self.heap.clone().into_sorted_vec();
+        let mut out = Vec::with_capacity(1 + 8 * (2 + values.len()));
+        out.push(SERIAL_VERSION);
+        out.extend_from_slice(&(self.k as u64).to_le_bytes());
+        out.extend_from_slice(&(values.len() as u64).to_le_bytes());
+        for v in values {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out
+    }
+
+    /// Decode a sketch written by `serialize`; it keeps accepting `update`s
+    /// exactly as the original would, including when under-filled.
+    pub fn deserialize(bytes: &[u8]) -> Result<KmvSketch, SketchError> {
+        let (&version, rest) = bytes
+            .split_first()
+            .ok_or(SketchError::DeserializeFailed("empty input"))?;
+        if version != SERIAL_VERSION {
+            return Err(SketchError::DeserializeFailed("unsupported format version"));
+        }
+        let mut words = rest.chunks_exact(8);
+        if !words.remainder().is_empty() {
+            return Err(SketchError::DeserializeFailed("truncated input"));
+        }
+        let mut next = || {
+            words
+                .next()
+                .map(|w| u64::from_le_bytes(w.try_into().expect("8-byte chunk")))
+                .ok_or(SketchError::DeserializeFailed("truncated input"))
+        };
+        let k = next()? as usize;
+        let len = next()? as usize;
+        if k == 0 {
+            return Err(SketchError::DeserializeFailed("k is zero"));
+        }
+        if len > k {
+            return Err(SketchError::DeserializeFailed("more values than k"));
+        }
+        if len.checked_add(2).and_then(|n| n.checked_mul(8)) != Some(rest.len()) {
+            return Err(SketchError::DeserializeFailed(
+                "length does not match value count",
+            ));
+        }
+        // Size the heap from the value count, not the untrusted `k`.
+        let mut sketch = KmvSketch {
+            k,
+            heap: BinaryHeap::with_capacity(len + 1),
+            members: HashSet::with_capacity_and_hasher(len, FxBuildHasher),
+            updates: 0,
+        };
+        let mut prev = None;
+        for _ in 0..len {
+            let v = next()?;
+            if prev.is_some_and(|p| p >= v) {
+                return Err(SketchError::DeserializeFailed(
+                    "values not strictly ascending",
+                ));
             }
+            prev = Some(v);
+            sketch.heap.push(v);
+            sketch.members.insert(v);
         }
+        Ok(sketch)
+    }
+
+    /// Rebuild the heap from the distinct union of current and incoming values.
+    fn absorb(&mut self, incoming: impl Iterator<Item = u64>) {
+        let mut all: Vec<u64> = self.heap.drain().collect();
+        all.extend(incoming);
+        all.sort_unstable();
+        all.dedup();
+        all.truncate(self.k);
+        self.members.clear();
+        self.members.extend(all.iter().copied());
+        self.heap.extend(all);
     }
 
     /// Produce a fixed-length signature by sorting ascending.
     /// If fewer than k items were observed, the signature will be shorter.
-    pub fn finish(mut self) -> Vec<u64> {
-        let mut out = Vec::with_capacity(self.heap.len());
-        while let Some(Reverse(v)) = self.heap.pop() {
-            out.push(v);
+    pub fn finish(self) -> Vec<u64> {
+        self.heap.into_sorted_vec()
+    }
+}
+
+/// Finish a pool of sketches; `out[i]` is `sketches[i].finish()`.
+pub fn finish_many(sketches: Vec<KmvSketch>) -> Vec<Vec<u64>> {
+    sketches.into_iter().map(KmvSketch::finish).collect()
+}
+
+/// Parallel `finish_many`: each sketch is sorted on rayon's global pool, and
+/// the output keeps the input order.
+#[cfg(feature = "parallel")]
+pub fn finish_many_parallel(sketches: Vec<KmvSketch>) -> Vec<Vec<u64>> {
+    sketches.into_par_iter().map(KmvSketch::finish).collect()
+}
+
+/// Filler appended by [`pad_signature`].
+pub const PAD_VALUE: u64 = u64::MAX;
+
+/// Extend a short KMV signature to length `k` with [`PAD_VALUE`] so it can be
+/// inserted into an `LshIndex` whose `signature_len()` is `k`. Signatures of
+/// length `>= k` are left untouched. The result stays ascending.
+///
+/// Padding is not neutral: padded positions match across all short
+/// signatures, so bands made only of padding collide for every pair of short
+/// sequences. Require more collisions than such bands can supply, or verify
+/// candidates, and estimate similarity with `sketch::jaccard_kmv` on the
+/// unpadded signatures, since shared padding would count as shared minima.
+pub fn pad_signature(sig: &mut Vec<u64>, k: usize) {
+    if sig.len() < k {
+        sig.resize(k, PAD_VALUE);
+    }
+}
+
+/// Bottom-k sketch whose capacity grows geometrically with the number of
+/// distinct values seen, from `initial_k` up to `max_k`.
+///
+/// Below the cap nothing is ever evicted (capacity doubles instead), so the
+/// kept set is always the exact bottom-`min(n, max_k)` and `finish` returns
+/// the same signature as `KmvSketch::new(max_k)` on the same input; compare
+/// signatures with `sketch::jaccard_kmv(a, b, max_k)`. Accuracy is therefore
+/// that of the fixed sketch at `max_k`, while memory is
+/// O(min(distinct values, max_k)) instead of being reserved up front. The
+/// price is an occasional reallocation while growing.
+#[derive(Clone)]
+pub struct AdaptiveKmvSketch {
+    k: usize,
+    max_k: usize,
+    heap: BinaryHeap<u64>, // max-heap, as in `KmvSketch`
+    members: HashSet<u64, FxBuildHasher>,
+}
+
+impl AdaptiveKmvSketch {
+    /// # Panics
+    /// If `initial_k == 0` or `initial_k > max_k`.
+    pub fn new(initial_k: usize, max_k: usize) -> Self {
+        assert!(
+            initial_k > 0 && initial_k <= max_k,
+            "need 0 < initial_k <= max_k"
+        );
+        Self {
+            k: initial_k,
+            max_k,
+            heap: BinaryHeap::with_capacity(initial_k + 1),
+            members: HashSet::with_capacity_and_hasher(initial_k, FxBuildHasher),
         }
-        out.sort_unstable();
-        out
+    }
+
+    /// Update with a pre-hashed k-mer value (u64). Repeated values are ignored.
+    #[inline]
+    pub fn update(&mut self, h: u64) {
+        if self.heap.len() == self.k && self.k < self.max_k {
+            if self.members.insert(h) {
+                // Full but below the cap: grow rather than evict.
+                self.k = self.k.saturating_mul(2).min(self.max_k);
+                self.heap.reserve(self.k + 1 - self.heap.len());
+                self.heap.push(h);
+            }
+            return;
+        }
+        if self.heap.len() < self.k {
+            if self.members.insert(h) {
+                self.heap.push(h);
+            }
+        } else if let Some(&top) = self.heap.peek() {
+            if h < top && self.members.insert(h) {
+                self.members.remove(&top);
+                self.heap.pop();
+                self.heap.push(h);
+            }
+        }
+    }
+
+    /// Current capacity; reaches `max_k` once that many distinct values arrive.
+    #[inline]
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    #[inline]
+    pub fn max_k(&self) -> usize {
+        self.max_k
+    }
+
+    /// Number of distinct minima currently held (at most `max_k`).
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Ascending bottom-`min(n, max_k)` signature.
+    pub fn finish(self) -> Vec<u64> {
+        self.heap.into_sorted_vec()
     }
 }
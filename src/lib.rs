@@ -15,8 +15,8 @@
 //! use kira_cdh_compat_lsh::*;
 //!
 //! // Suppose you already have hashed k-mers for sequences:
-//! let seq_a: Vec<u64> = vec![1, 2, 3, 10, 11, 12];
-//! let seq_b: Vec<u64> = vec![2, 3, 4, 11, 12, 13];
+//! let seq_a: Vec<u64> = (0..1000).map(util::splitmix64).collect();
+//! let seq_b: Vec<u64> = (0..950).chain(2000..2050).map(util::splitmix64).collect();
 //!
 //! // Build a KMV sketch (fast single-hash approach):
 //! let mut kmv = kmv::KmvSketch::new(128);
@@ -31,8 +31,8 @@
 //! let params = lsh::LshParams::new(32, 4).unwrap();
 //!
 //! let mut index = lsh::LshIndex::with_params(params.clone());
-//! index.insert(0, &sig_a);
-//! index.insert(1, &sig_b);
+//! index.insert(0, &sig_a).unwrap();
+//! index.insert(1, &sig_b).unwrap();
 //! index.build(); // finalize buckets (optional no-op for current implementation)
 //!
 //! // Query candidates for seq_a's signature:
@@ -40,15 +40,36 @@
 //! // cands is Vec<(id, collisions)>
 //! assert!(cands.iter().any(|(id, _)| *id == 1));
 //!
-//! // Jaccard estimate (KMV or MinHash signatures):
-//! let j_est = sketch::jaccard_from_signatures(&sig_a, &sig_b);
+//! // Jaccard estimate from the bottom-k of the union (KMV signatures):
+//! let j_est = sketch::jaccard_kmv(&sig_a, &sig_b, 128);
 //! eprintln!("Estimated Jaccard: {:.3}", j_est);
 //! ```
 //!
 //! # Notes
-//! - For MinHash, use `minhash::MinHash` with `num_hashes = bands * rows`.
+//! - For MinHash, use `minhash::MinHash` with `num_hashes = bands * rows`,
+//!   and `sketch::jaccard_from_signatures` to estimate similarity.
+//! - For KMV, estimate similarity with `sketch::jaccard_kmv`.
 //! - For KMV, you might prefer slightly larger k to reach stable estimates.
-//! - LSH banding is deterministic and uses splitmix64 to map bands to buckets.
+//! - LSH banding is deterministic and uses splitmix64 to map bands to buckets;
+//!   each band gets its own seed derived from the index seed.
+//!
+//! # `no_std`
+//! Disabling default features builds the crate as `no_std` with `alloc`
+//! (e.g. for sketching in WASM). All sketches, estimators and `LshIndex`
+//! remain available, except APIs that need floating-point functions from
+//! `std`: `LshParams::{for_threshold, probability_at, error_profile,
+//! recall_at, false_positive_at, min_collisions_for_recall}`,
+//! `LshIndex::{query_with_jaccard, band_entropy}`,
+//! `sketch::jaccard_with_ci` and `minhash::WeightedMinHash`. The `parallel`
+//! and `simd` features imply `std`.
+//!
+//! No atomics are required, so this also covers targets such as
+//! `thumbv6m-none-eabi` and `riscv32imc-unknown-none-elf`; without 64-bit
+//! atomics `LshStats::skipped_bucket_lookups` is not counted and stays 0.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 pub mod errors;
 pub mod kmv;
@@ -57,4 +78,7 @@ pub mod minhash;
 pub mod sketch;
 pub mod util;
 
-pub use lsh::{LshIndex, LshParams};
+pub use lsh::{
+    BandHasher, CompactLshIndex, IndexId, LshEnsemble, LshIndex, LshParams, LshQueryBuffer,
+    LshStats, TieBreak,
+};
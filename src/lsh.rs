@@ -5,12 +5,25 @@
 //! 64-bit key and used to bucket sequence IDs.
 
 use crate::errors::LshError;
-use crate::util::hash_band;
+use crate::kmv::{KmvSketch, pad_signature};
+use crate::sketch::jaccard_from_signatures;
+use crate::util::{Mixer, SplitMix64, SplitMix64Rng, splitmix64};
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+use core::mem::size_of;
+use core::ops::Range;
 use hashbrown::HashMap;
 use rustc_hash::FxBuildHasher;
-use std::sync::Arc;
+use smallvec::SmallVec;
 
-#[derive(Clone, Debug)]
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LshParams {
     pub bands: usize,
     pub rows_per_band: usize,
@@ -31,45 +44,806 @@ impl LshParams {
     pub fn signature_len(&self) -> usize {
         self.bands * self.rows_per_band
     }
+
+    /// Rough heap size in bytes of an `LshIndex<u32>` holding `num_items`
+    /// signatures, for sizing jobs before building. `avg_bucket_fanout` is
+    /// the expected mean number of IDs per bucket (1.0 when almost all
+    /// signatures are distinct; values below 1.0 are treated as 1.0).
+    ///
+    /// Assumptions: every item adds one ID to each band, at 8 bytes per ID
+    /// (a 4-byte `u32` plus `Vec` growth slack); each bucket costs one
+    /// `(u64, Vec<u32>)` slot plus a control byte, times a HashMap overhead
+    /// factor of 1.5 for the load factor and power-of-two growth. Stored
+    /// signatures are not included. Compare with `LshStats::estimated_bytes`
+    /// after building.
+    pub fn estimate_bytes(&self, num_items: usize, avg_bucket_fanout: f64) -> usize {
+        const BYTES_PER_ID: f64 = 8.0;
+        const MAP_OVERHEAD: f64 = 1.5;
+        let slot = (size_of::<(u64, Vec<u32>)>() + 1) as f64;
+        let items = num_items as f64;
+        let buckets = items / avg_bucket_fanout.max(1.0);
+        let per_band = buckets * slot * MAP_OVERHEAD + items * BYTES_PER_ID;
+        (per_band * self.bands as f64) as usize
+    }
+
+    /// Near-square banding of `num_hashes`: `rows_per_band` is the largest
+    /// divisor not above `sqrt(num_hashes)` and `bands = num_hashes / rows`,
+    /// so `bands >= rows` (128 gives 16 x 8, 100 gives 10 x 10). A sensible
+    /// default when no target threshold is known; see `for_threshold`
+    /// otherwise. Fails with `LshParamsError::Prime` when `num_hashes` is
+    /// prime, since its only factorizations are `1 x n` and `n x 1`.
+    pub fn balanced(num_hashes: usize) -> Result<Self, LshParamsError> {
+        if num_hashes == 0 {
+            return Err(LshParamsError::Zero);
+        }
+        let rows = (1..=num_hashes)
+            .take_while(|r| r * r <= num_hashes)
+            .filter(|r| num_hashes % r == 0)
+            .last()
+            .expect("1 divides every num_hashes");
+        if rows == 1 && num_hashes > 1 {
+            return Err(LshParamsError::Prime(num_hashes));
+        }
+        Self::new(num_hashes / rows, rows)
+    }
+
+    /// Pick the `(bands, rows_per_band)` factorization of `num_hashes` whose
+    /// S-curve crosses 0.5 closest to `target_jaccard`.
+    #[cfg(feature = "std")]
+    pub fn for_threshold(num_hashes: usize, target_jaccard: f64) -> Result<Self, LshParamsError> {
+        if num_hashes == 0 {
+            return Err(LshParamsError::Zero);
+        }
+        if !(target_jaccard > 0.0 && target_jaccard < 1.0) {
+            return Err(LshParamsError::InvalidThreshold(target_jaccard));
+        }
+        let mut best: Option<(f64, Self)> = None;
+        for rows in (1..=num_hashes).filter(|r| num_hashes % r == 0) {
+            let bands = num_hashes / rows;
+            // Solve 1 - (1 - s^r)^b = 0.5 for s.
+            let midpoint = (1.0 - 0.5f64.powf(1.0 / bands as f64)).powf(1.0 / rows as f64);
+            let gap = (midpoint - target_jaccard).abs();
+            if best.as_ref().is_none_or(|(g, _)| gap < *g) {
+                best = Some((
+                    gap,
+                    Self {
+                        bands,
+                        rows_per_band: rows,
+                    },
+                ));
+            }
+        }
+        Ok(best
+            .map(|(_, p)| p)
+            .expect("num_hashes > 0 has at least one divisor"))
+    }
+
+    /// Probability that a pair with Jaccard `jaccard` collides in at least one
+    /// band: `1 - (1 - s^r)^b`.
+    #[cfg(feature = "std")]
+    pub fn probability_at(&self, jaccard: f64) -> f64 {
+        let per_band = jaccard.powi(self.rows_per_band as i32);
+        1.0 - (1.0 - per_band).powi(self.bands as i32)
+    }
+
+    /// `(threshold, probability_at(threshold))` for each threshold, i.e. points
+    /// on the S-curve for plotting.
+    #[cfg(feature = "std")]
+    pub fn error_profile(&self, thresholds: &[f64]) -> Vec<(f64, f64)> {
+        thresholds
+            .iter()
+            .map(|&s| (s, self.probability_at(s)))
+            .collect()
+    }
+
+    /// Largest `min_collisions` that still retains a pair with Jaccard
+    /// `jaccard` with probability at least `target_recall`. Each band
+    /// collides independently with probability `s^r`, so the collision count
+    /// is Binomial(`bands`, `s^r`) and this picks the largest `t` with
+    /// `P[collisions >= t] >= target_recall`. Raising the threshold only
+    /// lowers recall, so any smaller threshold also meets the target (with
+    /// more false positives). Returns 0 when even one collision cannot reach
+    /// the target.
+    #[cfg(feature = "std")]
+    pub fn min_collisions_for_recall(&self, jaccard: f64, target_recall: f64) -> usize {
+        let p = jaccard.powi(self.rows_per_band as i32);
+        // pmf[c] = P[collisions == c], built band by band.
+        let mut pmf = vec![0.0; self.bands + 1];
+        pmf[0] = 1.0;
+        for n in 0..self.bands {
+            for c in (0..=n + 1).rev() {
+                let hit = if c > 0 { pmf[c - 1] * p } else { 0.0 };
+                pmf[c] = pmf[c] * (1.0 - p) + hit;
+            }
+        }
+        let mut tail = 0.0;
+        for t in (1..=self.bands).rev() {
+            tail += pmf[t];
+            if tail >= target_recall {
+                return t;
+            }
+        }
+        0
+    }
+
+    /// Chance that a true match with Jaccard `jaccard` is retrieved; the
+    /// false-negative rate is `1 - recall_at(jaccard)`.
+    #[inline]
+    #[cfg(feature = "std")]
+    pub fn recall_at(&self, jaccard: f64) -> f64 {
+        self.probability_at(jaccard)
+    }
+
+    /// Chance that a pair with Jaccard `jaccard` below your identity threshold
+    /// still becomes a candidate. Same curve as `recall_at`; the name reflects
+    /// which side of the threshold `jaccard` lies on.
+    #[inline]
+    #[cfg(feature = "std")]
+    pub fn false_positive_at(&self, jaccard: f64) -> f64 {
+        self.probability_at(jaccard)
+    }
 }
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug)]
 pub enum LshParamsError {
-    #[error("bands and rows_per_band must be non-zero")]
     Zero,
+
+    InvalidThreshold(f64),
+
+    Prime(usize),
+}
+
+impl fmt::Display for LshParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LshParamsError::Zero => f.write_str("bands and rows_per_band must be non-zero"),
+            LshParamsError::InvalidThreshold(t) => write!(
+                f,
+                "target Jaccard must lie strictly between 0 and 1 (got {t})"
+            ),
+            LshParamsError::Prime(n) => write!(
+                f,
+                "num_hashes={n} is prime and has no non-trivial bands x rows factorization"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for LshParamsError {}
+
+/// Bucket-distribution and memory statistics of an [`LshIndex`], see
+/// [`LshIndex::stats`].
+#[derive(Clone, Debug)]
+pub struct LshStats {
+    /// Number of distinct band keys (buckets) in each band.
+    pub buckets_per_band: Vec<usize>,
+    /// Largest bucket across all bands.
+    pub max_bucket_size: usize,
+    /// Mean bucket size across all bands (0 for an empty index).
+    pub mean_bucket_size: f64,
+    /// ID entries across all buckets; each inserted signature adds one per band.
+    pub total_ids: usize,
+    /// Rough heap footprint: map slots and control bytes, bucket `Vec`
+    /// capacities and stored signatures. Allocator overhead is not included.
+    pub estimated_bytes: usize,
+    /// Buckets currently larger than the index's `max_bucket_size`.
+    pub oversized_buckets: usize,
+    /// Bucket lookups skipped by queries so far because of `max_bucket_size`.
+    /// Always 0 on targets without 64-bit atomics, where it is not counted.
+    pub skipped_bucket_lookups: u64,
+}
+
+impl fmt::Display for LshStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let buckets: usize = self.buckets_per_band.iter().sum();
+        let min_band = self.buckets_per_band.iter().min().copied().unwrap_or(0);
+        let max_band = self.buckets_per_band.iter().max().copied().unwrap_or(0);
+        write!(
+            f,
+            "bands={} buckets={} (per band {}..={}) ids={} bucket_size(max={}, mean={:.2}) oversized={} skipped={} bytes~{}",
+            self.buckets_per_band.len(),
+            buckets,
+            min_band,
+            max_band,
+            self.total_ids,
+            self.max_bucket_size,
+            self.mean_bucket_size,
+            self.oversized_buckets,
+            self.skipped_bucket_lookups,
+            self.estimated_bytes
+        )
+    }
+}
+
+/// Bounds required of sequence IDs stored in an [`LshIndex`]. Implemented for
+/// every type that satisfies them (`u32`, `u64`, newtypes deriving the traits).
+pub trait IndexId: Copy + Eq + Hash + Ord {}
+
+impl<T: Copy + Eq + Hash + Ord> IndexId for T {}
+
+/// Scratch space for [`LshIndex::query_candidates_buf`]. Keep one per thread
+/// and pass it to every query to avoid reallocating the collision map.
+pub struct LshQueryBuffer<I = u32> {
+    counts: Collisions<I>,
+    out: Vec<(I, u32)>,
+}
+
+impl<I> LshQueryBuffer<I> {
+    pub fn new() -> Self {
+        Self {
+            counts: HashMap::with_hasher(FxBuildHasher),
+            out: Vec::new(),
+        }
+    }
+}
+
+impl<I> Default for LshQueryBuffer<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How ranked queries order candidates with equal collision counts (or
+/// equal scores, for `query_candidates_weighted`).
+///
+/// Every sorted query of `LshIndex`, `CompactLshIndex` and `LshEnsemble`
+/// applies it. It has no effect on the unsorted `query_candidates_unsorted`,
+/// `query_candidates_into` and `candidates_iter`, nor on `candidate_pairs*`,
+/// whose pairs are ordered by their IDs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TieBreak {
+    #[default]
+    LowerId,
+    HigherId,
+    /// First-inserted ID first. An ID removed completely and inserted again
+    /// counts as new.
+    InsertionOrder,
+}
+
+/// Function folding a band's rows into its bucket key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BandHasher {
+    /// [`hash_band`](crate::util::hash_band) (splitmix64 chain, or the
+    /// index's [`Mixer`]): fastest, but collisions can be
+    /// forced by anyone who knows the algorithm.
+    #[default]
+    SplitMix,
+    /// SipHash-2-4 keyed by the index seed (feature `sip`). Bucket
+    /// assignment is only unpredictable if the seed is: the key is derived
+    /// entirely from it, and the default seed of `with_params` is a public
+    /// constant. To resist bucket flooding by untrusted input, construct the
+    /// index with a secret random seed via `with_band_hasher`. Several times
+    /// slower than `SplitMix`.
+    #[cfg(feature = "sip")]
+    Sip,
 }
 
-/// Read-only finalized index.
-pub struct LshIndex {
+/// Read-only finalized index, generic over the ID type (default `u32`) and
+/// the band-folding [`Mixer`] (default [`SplitMix64`], see `with_mixer`).
+pub struct LshIndex<I = u32, M = SplitMix64> {
     params: LshParams,
     // For each band, map band-key -> Vec<id>
-    bands: Vec<HashMap<u64, Vec<u32>, FxBuildHasher>>,
+    bands: Vec<HashMap<u64, Vec<I>, FxBuildHasher>>,
     // Optional global store of signatures if you want to re-query without passing a signature.
-    // We keep it off by default to avoid duplication; use the map below for convenience.
-    #[allow(dead_code)]
-    ids: Vec<u32>,
-    #[allow(dead_code)]
-    signatures: Vec<Arc<Vec<u64>>>,
+    // We keep it off by default to avoid duplication; see `with_params_storing`.
+    signatures: Option<HashMap<I, Vec<u64>, FxBuildHasher>>,
     seed: u64,
+    band_hasher: BandHasher,
+    // Buckets larger than this are skipped during queries (see `set_max_bucket_size`).
+    max_bucket_size: Option<usize>,
+    // Number of bucket lookups skipped because of `max_bucket_size`.
+    skipped_buckets: SkipCounter,
+    // Inserted signatures not yet removed.
+    len: usize,
+    // id -> live insertion count and first-insertion sequence number.
+    ids: HashMap<I, IdEntry, FxBuildHasher>,
+    next_seq: u64,
+    tie_break: TieBreak,
+    // Reject inserts whose signature is longer than `bands * rows`.
+    strict: bool,
+    mixer: M,
+}
+
+/// Counter of bucket lookups skipped by `&self` queries. Targets without
+/// 64-bit atomics (e.g. thumbv6m, riscv32imc) do not count.
+#[derive(Default)]
+struct SkipCounter {
+    #[cfg(target_has_atomic = "64")]
+    skipped: core::sync::atomic::AtomicU64,
+}
+
+impl SkipCounter {
+    #[inline]
+    fn bump(&self) {
+        #[cfg(target_has_atomic = "64")]
+        self.skipped
+            .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        #[cfg(target_has_atomic = "64")]
+        return self.skipped.load(core::sync::atomic::Ordering::Relaxed);
+        #[cfg(not(target_has_atomic = "64"))]
+        0
+    }
+
+    fn reset(&mut self) {
+        #[cfg(target_has_atomic = "64")]
+        self.skipped.store(0, core::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IdEntry {
+    count: u32,
+    seq: u64,
 }
 
-impl LshIndex {
+impl<I: IndexId> LshIndex<I> {
     pub fn with_params(params: LshParams) -> Self {
+        LshIndex::with_mixer(params, 0xC0FFEEFADEu64, SplitMix64) // deterministic default seed
+    }
+
+    /// Index with an explicit base seed; per-band seeds are derived from it.
+    ///
+    /// Indexes built with different seeds are independent LSH tables, e.g. for
+    /// ensembles whose candidates are unioned. Signatures themselves do not
+    /// depend on the seed, but band keys do: only compare or combine results
+    /// of indexes whose `seed()` matches.
+    pub fn with_params_and_seed(params: LshParams, seed: u64) -> Self {
+        let mut index = Self::with_params(params);
+        index.seed = seed;
+        index
+    }
+
+    /// Alias of [`with_params_and_seed`](Self::with_params_and_seed).
+    #[inline]
+    pub fn with_seed(params: LshParams, seed: u64) -> Self {
+        Self::with_params_and_seed(params, seed)
+    }
+
+    /// Index with an explicit band-key function, e.g. `BandHasher::Sip` with
+    /// a secret `seed` for untrusted input. Indexes only agree on bucket keys
+    /// when params, seed and hasher all match.
+    pub fn with_band_hasher(params: LshParams, seed: u64, hasher: BandHasher) -> Self {
+        Self::with_params_and_seed(params, seed).using_band_hasher(hasher)
+    }
+
+    /// Like `with_params`, with room for `expected_items` inserts reserved up
+    /// front (see `reserve`) so a build of known size does not rehash.
+    pub fn with_capacity(params: LshParams, expected_items: usize) -> Self {
+        let mut index = Self::with_params(params);
+        index.reserve(expected_items);
+        index
+    }
+
+    /// Like `with_params`, but `insert` also keeps a copy of each signature so
+    /// the index can be re-queried by ID (`query_by_id`) and candidates can be
+    /// scored against full signatures (`refine`).
+    pub fn with_params_storing(params: LshParams) -> Self {
+        Self::with_params(params).storing_signatures()
+    }
+}
+
+impl<I: IndexId, M: Mixer> LshIndex<I, M> {
+    /// Index whose `BandHasher::SplitMix` band keys are folded with `mixer`
+    /// instead of splitmix64. Build and query with the same mixer: it is not
+    /// stored, and indexes only agree on bucket keys when params, seed,
+    /// hasher and mixer all match.
+    ///
+    /// The options of the other constructors apply to any mixer through
+    /// `storing_signatures`, `using_band_hasher` and `reserve`, e.g.
+    /// `LshIndex::with_mixer(params, seed, mixer).storing_signatures()`.
+    pub fn with_mixer(params: LshParams, seed: u64, mixer: M) -> Self {
         let mut bands = Vec::with_capacity(params.bands);
         for _ in 0..params.bands {
-            bands.push(HashMap::with_hasher(FxBuildHasher::default()));
+            bands.push(HashMap::with_hasher(FxBuildHasher));
         }
         Self {
             params,
             bands,
-            ids: Vec::new(),
-            signatures: Vec::new(),
-            seed: 0xC0FFEEFADEu64, // deterministic default
+            signatures: None,
+            seed,
+            band_hasher: BandHasher::SplitMix,
+            max_bucket_size: None,
+            skipped_buckets: SkipCounter::default(),
+            len: 0,
+            ids: HashMap::with_hasher(FxBuildHasher),
+            next_seq: 0,
+            tie_break: TieBreak::LowerId,
+            strict: false,
+            mixer,
+        }
+    }
+
+    /// Builder form of `with_params_storing`: `insert` also keeps a copy of
+    /// each signature, for `query_by_id`, `refine` and the verified queries.
+    /// Entries inserted before this call have no stored signature.
+    pub fn storing_signatures(mut self) -> Self {
+        self.signatures
+            .get_or_insert_with(|| HashMap::with_hasher(FxBuildHasher));
+        self
+    }
+
+    /// Builder form of `with_band_hasher`: key bands with `hasher`.
+    ///
+    /// # Panics
+    /// If the index is not empty, since existing entries were keyed with the
+    /// previous hasher.
+    pub fn using_band_hasher(mut self, hasher: BandHasher) -> Self {
+        assert!(
+            self.is_empty(),
+            "band hasher must be chosen before inserting"
+        );
+        self.band_hasher = hasher;
+        self
+    }
+
+    /// Order of equal-collision candidates in every sorted query (see
+    /// [`TieBreak`]). Defaults to `TieBreak::LowerId`.
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    #[inline]
+    pub fn tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// Strict mode: inserts (`insert`, `insert_unique`, `insert_if_novel`,
+    /// `insert_bulk_parallel`) fail with `LshError::LengthMismatch` unless
+    /// the signature is exactly `bands * rows_per_band` long, catching
+    /// configuration drift between sketching and indexing. Off by default;
+    /// queries always accept longer signatures and use their prefix.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    #[inline]
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Count one more live insertion of `id`, assigning it the next sequence
+    /// number if it is new.
+    #[inline]
+    fn note_inserted(&mut self, id: I, n: u32) {
+        note_id(&mut self.ids, &mut self.next_seq, id, n);
+    }
+
+    /// Treat buckets holding more than `max` IDs as uninformative: queries
+    /// skip them and `stats` reports them. `None` (the default) disables the cap.
+    ///
+    /// Low-complexity or repetitive sequences pile into a few mega-buckets
+    /// that barely discriminate yet dominate query latency; skipping them
+    /// trades a little recall for large speedups on real genomic data.
+    pub fn set_max_bucket_size(&mut self, max: Option<usize>) {
+        self.max_bucket_size = max;
+    }
+
+    #[inline]
+    pub fn max_bucket_size(&self) -> Option<usize> {
+        self.max_bucket_size
+    }
+
+    #[inline]
+    pub fn band_hasher(&self) -> BandHasher {
+        self.band_hasher
+    }
+
+    /// Base seed used to derive per-band seeds.
+    #[inline]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Effective seed of each band, `splitmix64(seed ^ splitmix64(b))`, for
+    /// provenance records: indexes with equal params and band seeds bucket
+    /// identically.
+    pub fn band_seeds(&self) -> Vec<u64> {
+        (0..self.params.bands)
+            .map(|b| band_seed(self.seed, b))
+            .collect()
+    }
+
+    /// Insert a signature for `id`. Only the first `bands*rows` values are
+    /// banded; a longer signature is accepted unless the index is strict
+    /// (see `set_strict`).
+    pub fn insert(&mut self, id: I, signature: &[u64]) -> Result<(), LshError> {
+        self.insert_rows(id, signature)
+    }
+
+    /// Insert a 32-bit signature (e.g. `minhash::MinHash32`). Bands fold the
+    /// u32 rows directly into the same keys as the zero-extended signature,
+    /// so u32 and u64 inserts and queries can be mixed; pair with
+    /// `query_candidates_u32`.
+    ///
+    /// The index never holds signatures except in storing mode, where it
+    /// keeps a widened u64 copy: the halved size of u32 signatures only saves
+    /// memory in the caller's own signature storage.
+    pub fn insert_u32(&mut self, id: I, signature: &[u32]) -> Result<(), LshError> {
+        self.insert_rows(id, signature)
+    }
+
+    fn insert_rows<R: Row>(&mut self, id: I, signature: &[R]) -> Result<(), LshError> {
+        self.check_insert_signature(signature)?;
+        // Insert into each band's bucket.
+        let (params, seed, hasher) = (&self.params, self.seed, self.band_hasher);
+        let (bands, mixer) = (&mut self.bands, &self.mixer);
+        for_each_band_key(signature, params, seed, hasher, mixer, |b, key| {
+            bands[b].entry(key).or_default().push(id);
+        });
+        if let Some(store) = &mut self.signatures {
+            store.insert(id, signature.iter().map(|&v| v.widen()).collect());
+        }
+        self.len += 1;
+        self.note_inserted(id, 1);
+        Ok(())
+    }
+
+    /// Sketch `hashes` (pre-hashed k-mers) into a bottom-`k` KMV signature,
+    /// pad it with `kmv::pad_signature` if the sequence was too short to fill
+    /// it, and insert it under `id`.
+    ///
+    /// `k` must equal `bands * rows_per_band`, or `LshError::InvalidParams`
+    /// is returned. See `pad_signature` for how padding affects candidates.
+    pub fn insert_kmers(
+        &mut self,
+        id: I,
+        hashes: impl IntoIterator<Item = u64>,
+        k: usize,
+    ) -> Result<(), LshError> {
+        let signature = self.kmer_signature(hashes, k)?;
+        self.insert(id, &signature)
+    }
+
+    // Padded bottom-`k` KMV signature shared by `insert_kmers` and `query_kmers`.
+    fn kmer_signature(
+        &self,
+        hashes: impl IntoIterator<Item = u64>,
+        k: usize,
+    ) -> Result<Vec<u64>, LshError> {
+        if k != self.params.signature_len() {
+            return Err(LshError::InvalidParams {
+                bands: self.params.bands,
+                rows: self.params.rows_per_band,
+                sig_len: k,
+            });
+        }
+        let mut sketch = KmvSketch::new(k);
+        for h in hashes {
+            sketch.update(h);
+        }
+        let mut signature = sketch.finish();
+        pad_signature(&mut signature, k);
+        Ok(signature)
+    }
+
+    /// Like `insert`, but fails with `LshError::AlreadyPresent` instead of
+    /// silently adding a second entry for an `id` already in the index.
+    pub fn insert_unique(&mut self, id: I, signature: &[u64]) -> Result<(), LshError> {
+        if self.contains(id) {
+            return Err(LshError::AlreadyPresent);
+        }
+        self.insert(id, signature)
+    }
+
+    /// Insert `signature` only if no indexed entry collides with it in more
+    /// than `max_collisions` bands; returns whether it was inserted. This is
+    /// the representative-selection step of greedy (CD-HIT-style) clustering:
+    /// a sequence too similar to an existing representative is not indexed.
+    ///
+    /// Buckets above `max_bucket_size` are ignored as in queries. Fails like
+    /// `insert` on a short signature.
+    pub fn insert_if_novel(
+        &mut self,
+        id: I,
+        signature: &[u64],
+        max_collisions: usize,
+    ) -> Result<bool, LshError> {
+        self.check_insert_signature(signature)?;
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        if counts.values().any(|&(c, _)| c as usize > max_collisions) {
+            return Ok(false);
+        }
+        self.insert(id, signature)?;
+        Ok(true)
+    }
+
+    /// Whether `id` has been inserted and not removed since.
+    #[inline]
+    pub fn contains(&self, id: I) -> bool {
+        self.ids.contains_key(&id)
+    }
+
+    /// Insert many signatures at once, one rayon task per band.
+    ///
+    /// Every signature is validated before anything is inserted. Each band's
+    /// map is written by a single thread in input order, so the result is
+    /// identical to calling `insert` serially. Thread count follows rayon's
+    /// global pool (`RAYON_NUM_THREADS` or `ThreadPoolBuilder::build_global`).
+    #[cfg(feature = "parallel")]
+    pub fn insert_bulk_parallel(&mut self, items: &[(I, Vec<u64>)]) -> Result<(), LshError>
+    where
+        I: Send + Sync,
+        M: Sync,
+    {
+        for (_, sig) in items {
+            self.check_insert_signature(sig)?;
+        }
+        let rows = self.params.rows_per_band;
+        let (seed, hasher, mixer) = (self.seed, self.band_hasher, &self.mixer);
+        self.bands.par_iter_mut().enumerate().for_each(|(b, map)| {
+            for (id, sig) in items {
+                map.entry(band_key(sig, rows, seed, hasher, mixer, b))
+                    .or_default()
+                    .push(*id);
+            }
+        });
+        if let Some(store) = &mut self.signatures {
+            for (id, sig) in items {
+                store.insert(*id, sig.clone());
+            }
+        }
+        self.len += items.len();
+        for (id, _) in items {
+            self.note_inserted(*id, 1);
+        }
+        Ok(())
+    }
+
+    /// Remove `id` from every bucket it was inserted into.
+    ///
+    /// Callers must pass the same signature that was used for `insert`, since
+    /// the band keys are recomputed from it. If any band's bucket for
+    /// `signature` does not hold `id`, the signature was not inserted under
+    /// `id` and nothing is removed, so the buckets and `len`/`contains` never
+    /// disagree. Buckets left empty are pruned. A stored signature is dropped
+    /// only once no insert of `id` remains.
+    pub fn remove(&mut self, id: I, signature: &[u64]) -> Result<(), LshError> {
+        self.check_signature(signature)?;
+        let mut keys: SmallVec<[u64; 64]> = SmallVec::with_capacity(self.params.bands);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |_, key| keys.push(key),
+        );
+        let held = keys
+            .iter()
+            .enumerate()
+            .all(|(b, key)| self.bands[b].get(key).is_some_and(|ids| ids.contains(&id)));
+        if !held {
+            return Ok(());
+        }
+        for (b, key) in keys.into_iter().enumerate() {
+            let hashbrown::hash_map::Entry::Occupied(mut bucket) = self.bands[b].entry(key) else {
+                continue;
+            };
+            let before = bucket.get().len();
+            bucket.get_mut().retain(|&x| x != id);
+            if b == 0 {
+                // Every insert adds exactly one entry to band 0.
+                let removed = before - bucket.get().len();
+                self.len -= removed;
+                if let hashbrown::hash_map::Entry::Occupied(mut e) = self.ids.entry(id) {
+                    e.get_mut().count -= removed as u32;
+                    if e.get().count == 0 {
+                        e.remove();
+                        // Keep the stored signature while other inserts of
+                        // `id` are still indexed.
+                        if let Some(store) = &mut self.signatures {
+                            store.remove(&id);
+                        }
+                    }
+                }
+            }
+            if bucket.get().is_empty() {
+                bucket.remove();
+            }
         }
+        Ok(())
+    }
+
+    /// Absorb `other` (e.g. a shard built in parallel) by appending its bucket
+    /// ID lists to the matching buckets of `self`. Fails with
+    /// `LshError::IncompatibleIndex` unless params, seed and band hasher
+    /// match.
+    ///
+    /// IDs are not deduplicated: an ID present in both shards ends up with
+    /// two entries, exactly as if it had been inserted twice. Stored
+    /// signatures are merged only when `self` stores them (`other`'s win on
+    /// conflicts); `self` keeps its own `max_bucket_size`.
+    pub fn merge(&mut self, other: LshIndex<I, M>) -> Result<(), LshError> {
+        if self.params != other.params
+            || self.seed != other.seed
+            || self.band_hasher != other.band_hasher
+        {
+            return Err(LshError::IncompatibleIndex);
+        }
+        for (mine, theirs) in self.bands.iter_mut().zip(other.bands) {
+            for (key, ids) in theirs {
+                match mine.entry(key) {
+                    hashbrown::hash_map::Entry::Occupied(mut e) => e.get_mut().extend(ids),
+                    hashbrown::hash_map::Entry::Vacant(e) => {
+                        e.insert(ids);
+                    }
+                }
+            }
+        }
+        if let (Some(store), Some(theirs)) = (&mut self.signatures, other.signatures) {
+            store.extend(theirs);
+        }
+        self.len += other.len;
+        // IDs new to `self` rank after its own, in `other`'s insertion order.
+        let mut theirs: Vec<(I, IdEntry)> = other.ids.into_iter().collect();
+        theirs.sort_unstable_by_key(|&(_, e)| e.seq);
+        for (id, e) in theirs {
+            self.note_inserted(id, e.count);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn num_bands(&self) -> usize {
+        self.params.bands
+    }
+
+    /// Read-only `(key, ids)` view of every bucket in band `band`, for
+    /// custom candidate scoring. Iteration order is unspecified.
+    ///
+    /// # Panics
+    /// If `band >= num_bands()`.
+    pub fn iter_band(&self, band: usize) -> impl Iterator<Item = (u64, &[I])> + '_ {
+        self.bands[band]
+            .iter()
+            .map(|(&key, ids)| (key, ids.as_slice()))
+    }
+
+    /// The bucket key of every band for `signature`, whether or not it is
+    /// inserted: diffing two signatures' keys shows which bands collide.
+    /// Depends only on params, seed and band hasher.
+    ///
+    /// # Panics
+    /// If the signature is shorter than `bands * rows_per_band`.
+    pub fn band_keys(&self, signature: &[u64]) -> Vec<u64> {
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+        let mut keys = Vec::with_capacity(self.params.bands);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |_, key| keys.push(key),
+        );
+        keys
+    }
+
+    /// `check_signature`, plus `LshError::LengthMismatch` for any length
+    /// other than `bands * rows_per_band` when the index is strict.
+    fn check_insert_signature<R>(&self, signature: &[R]) -> Result<(), LshError> {
+        self.check_signature(signature)?;
+        let need = self.params.signature_len();
+        if self.strict && signature.len() != need {
+            return Err(LshError::LengthMismatch {
+                sig_len: signature.len(),
+                need,
+            });
+        }
+        Ok(())
     }
 
-    /// Insert a signature for `id`. Signature length must equal `bands*rows`.
-    pub fn insert(&mut self, id: u32, signature: &[u64]) -> Result<(), LshError> {
+    fn check_signature<R>(&self, signature: &[R]) -> Result<(), LshError> {
         let need = self.params.signature_len();
         if signature.len() < need {
             return Err(LshError::ShortSignature {
@@ -77,59 +851,1724 @@ impl LshIndex {
                 need,
             });
         }
-        // Insert into each band's bucket.
-        for b in 0..self.params.bands {
-            let start = b * self.params.rows_per_band;
-            let end = start + self.params.rows_per_band;
-            let key = hash_band(&signature[start..end], (b as u64) ^ self.seed);
-            self.bands[b].entry(key).or_default().push(id);
-        }
         Ok(())
     }
 
-    /// Optional finalize step (reserved for future compaction).
+    #[inline]
+    fn band_key(&self, signature: &[u64], b: usize) -> u64 {
+        band_key(
+            signature,
+            self.params.rows_per_band,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            b,
+        )
+    }
+
+    /// Number of inserted signatures (duplicates included) not yet removed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reserve room for `additional` more inserted signatures.
+    ///
+    /// Each insert adds at most one bucket per band, so every band map
+    /// reserves `additional` buckets: the exact need when bucket fan-out is
+    /// 1 (mostly distinct band keys, typical for non-redundant data), and an
+    /// over-estimate for redundant collections where many IDs share buckets.
+    /// The ID table and, for storing indexes, the signature store reserve
+    /// `additional` entries as well.
+    pub fn reserve(&mut self, additional: usize) {
+        for map in &mut self.bands {
+            map.reserve(additional);
+        }
+        self.ids.reserve(additional);
+        if let Some(store) = &mut self.signatures {
+            store.reserve(additional);
+        }
+    }
+
+    /// Remove every entry (and stored signature) while keeping `params`,
+    /// `seed`, `max_bucket_size` and the band maps' allocated capacity, so the
+    /// index can be refilled without reallocating.
+    pub fn clear(&mut self) {
+        for map in &mut self.bands {
+            map.clear();
+        }
+        if let Some(store) = &mut self.signatures {
+            store.clear();
+        }
+        self.skipped_buckets.reset();
+        self.len = 0;
+        self.ids.clear();
+        self.next_seq = 0;
+    }
+
+    /// Bucket-size distribution and an estimate of memory use, e.g. to spot
+    /// degenerate buckets absorbing most IDs (low-complexity sequences), which
+    /// suggests raising `rows_per_band`.
+    pub fn stats(&self) -> LshStats {
+        let mut buckets_per_band = Vec::with_capacity(self.bands.len());
+        let mut max_bucket_size = 0;
+        let mut total_ids = 0;
+        let mut estimated_bytes = 0;
+        let mut oversized_buckets = 0;
+        let cap = self.max_bucket_size.unwrap_or(usize::MAX);
+        for map in &self.bands {
+            buckets_per_band.push(map.len());
+            // One slot plus one control byte per bucket of capacity.
+            estimated_bytes += map.capacity() * (size_of::<(u64, Vec<I>)>() + 1);
+            for ids in map.values() {
+                max_bucket_size = max_bucket_size.max(ids.len());
+                total_ids += ids.len();
+                oversized_buckets += usize::from(ids.len() > cap);
+                estimated_bytes += ids.capacity() * size_of::<I>();
+            }
+        }
+        if let Some(store) = &self.signatures {
+            estimated_bytes += store.capacity() * (size_of::<(I, Vec<u64>)>() + 1);
+            for sig in store.values() {
+                estimated_bytes += sig.capacity() * size_of::<u64>();
+            }
+        }
+        let buckets: usize = buckets_per_band.iter().sum();
+        let mean_bucket_size = if buckets == 0 {
+            0.0
+        } else {
+            total_ids as f64 / buckets as f64
+        };
+        LshStats {
+            buckets_per_band,
+            max_bucket_size,
+            mean_bucket_size,
+            total_ids,
+            estimated_bytes,
+            oversized_buckets,
+            skipped_bucket_lookups: self.skipped_buckets.get(),
+        }
+    }
+
+    /// Shannon entropy (bits) of each band's bucket-size distribution, where
+    /// a bucket's probability is its share of the band's IDs. A band with all
+    /// IDs in one bucket scores 0 and separates nothing; the maximum, for all
+    /// singleton buckets, is `log2(len)`. Uninformative bands are candidates
+    /// for a zero weight in `query_candidates_weighted`. Empty bands score 0.
+    #[cfg(feature = "std")]
+    pub fn band_entropy(&self) -> Vec<f64> {
+        self.bands
+            .iter()
+            .map(|map| {
+                let total: usize = map.values().map(Vec::len).sum();
+                if total == 0 {
+                    return 0.0;
+                }
+                let total = total as f64;
+                map.values()
+                    .filter(|ids| !ids.is_empty())
+                    .map(|ids| {
+                        let p = ids.len() as f64 / total;
+                        -p * p.log2()
+                    })
+                    .sum::<f64>()
+                    .max(0.0)
+            })
+            .collect()
+    }
+
+    /// Finalize after bulk insertion: sorts each bucket, drops repeated IDs
+    /// within it (left by inserting the same ID and signature again), and
+    /// trims spare capacity. For a compact read-only layout, see `compile`.
+    ///
+    /// Repeats removed here no longer count towards `len`, as if the ID had
+    /// been inserted once.
     pub fn build(&mut self) {
-        // Currently a no-op: data is already in-place.
-        // Future: shrink_to_fit, sort buckets, convert to compact arenas, etc.
+        let mut deduped = false;
         for map in &mut self.bands {
             for (_k, v) in map.iter_mut() {
+                let before = v.len();
+                v.sort_unstable();
+                v.dedup();
+                deduped |= v.len() != before;
                 v.shrink_to_fit();
             }
         }
+        if deduped {
+            // Every insert adds exactly one entry to band 0, so recount from it.
+            for entry in self.ids.values_mut() {
+                entry.count = 0;
+            }
+            self.len = 0;
+            for ids in self.bands[0].values() {
+                self.len += ids.len();
+                for id in ids {
+                    if let Some(entry) = self.ids.get_mut(id) {
+                        entry.count += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Freeze the index into a [`CompactLshIndex`]: per band, one sorted key
+    /// array and one ID arena instead of a `Vec` per bucket. Stored
+    /// signatures are dropped; `max_bucket_size` carries over. Occasional
+    /// later inserts go through `CompactLshIndex::insert`.
+    pub fn compile(self) -> CompactLshIndex<I, M> {
+        let bands: Vec<CompactBand<I>> = self
+            .bands
+            .into_iter()
+            .map(|map| {
+                let mut buckets: Vec<(u64, Vec<I>)> = map.into_iter().collect();
+                buckets.sort_unstable_by_key(|&(key, _)| key);
+                CompactBand::from_sorted(buckets)
+            })
+            .collect();
+        CompactLshIndex {
+            params: self.params,
+            seed: self.seed,
+            band_hasher: self.band_hasher,
+            max_bucket_size: self.max_bucket_size,
+            overflow: (0..bands.len())
+                .map(|_| HashMap::with_hasher(FxBuildHasher))
+                .collect(),
+            bands,
+            overflow_len: 0,
+            overflow_limit: DEFAULT_OVERFLOW_LIMIT,
+            len: self.len,
+            tie_break: self.tie_break,
+            ids: self.ids,
+            next_seq: self.next_seq,
+            mixer: self.mixer,
+        }
     }
 
     /// Query candidates for a given signature. Returns (id, collisions) pairs.
     /// `min_collisions` is the minimum number of band collisions to report.
-    pub fn query_candidates(&self, signature: &[u64], min_collisions: usize) -> Vec<(u32, u32)> {
-        let need = self.params.signature_len();
+    /// Each band contributes at most one collision per candidate, even if the
+    /// ID was inserted into that bucket more than once, so collisions never
+    /// exceed `bands`.
+    ///
+    /// Only the first `bands * rows_per_band` values are used, so a longer
+    /// signature queries with its prefix.
+    ///
+    /// # Panics
+    /// If the signature is shorter than that; see `query_candidates_checked`.
+    pub fn query_candidates(&self, signature: &[u64], min_collisions: usize) -> Vec<(I, u32)> {
+        let mut buf = LshQueryBuffer::new();
+        self.query_with_counts(signature, min_collisions, None, &mut buf);
+        buf.out
+    }
+
+    /// `query_candidates` without the final sort, for callers that put the
+    /// results straight into a set. Returns exactly the same `(id, collisions)`
+    /// pairs; only the order differs (arbitrary, collision-map order).
+    pub fn query_candidates_unsorted(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+    ) -> Vec<(I, u32)> {
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        counts
+            .into_iter()
+            .filter(|&(_, (c, _))| (c as usize) >= min_collisions)
+            .map(|(id, (c, _))| (id, c))
+            .collect()
+    }
+
+    /// Query counterpart of `insert_kmers`: sketch `hashes` into a padded
+    /// bottom-`k` KMV signature and run `query_candidates` with it.
+    ///
+    /// `k` must equal `bands * rows_per_band` (else `LshError::InvalidParams`),
+    /// and the indexed entries must be KMV signatures built the same way, e.g.
+    /// by `insert_kmers`; MinHash entries share no band keys with KMV ones.
+    pub fn query_kmers(
+        &self,
+        hashes: impl IntoIterator<Item = u64>,
+        k: usize,
+        min_collisions: usize,
+    ) -> Result<Vec<(I, u32)>, LshError> {
+        let signature = self.kmer_signature(hashes, k)?;
+        Ok(self.query_candidates(&signature, min_collisions))
+    }
+
+    /// `query_candidates` for a 32-bit signature, folding its u32 rows
+    /// directly; see `insert_u32`.
+    pub fn query_candidates_u32(&self, signature: &[u32], min_collisions: usize) -> Vec<(I, u32)> {
+        let mut buf = LshQueryBuffer::new();
+        self.query_with_counts(signature, min_collisions, None, &mut buf);
+        buf.out
+    }
+
+    /// Number of candidates `query_candidates` would return, without
+    /// collecting or sorting them; e.g. to spot pathological queries with
+    /// huge candidate sets before paying to materialize them.
+    pub fn count_candidates(&self, signature: &[u64], min_collisions: usize) -> usize {
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        counts
+            .values()
+            .filter(|&&(c, _)| (c as usize) >= min_collisions)
+            .count()
+    }
+
+    /// `query_candidates` for dense IDs in `0..n_ids`, counting collisions in
+    /// flat arrays indexed by ID instead of a hash map: much faster for
+    /// all-vs-all runs over contiguous ID spaces. Same results and order as
+    /// `query_candidates`. Each call allocates `O(n_ids)` zeroed counters,
+    /// so keep `n_ids` close to the real ID range.
+    ///
+    /// # Panics
+    /// If an indexed ID colliding with the query is not in `0..n_ids`, or
+    /// the signature is too short.
+    pub fn query_candidates_dense(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+        n_ids: usize,
+    ) -> Vec<(I, u32)>
+    where
+        usize: TryFrom<I>,
+    {
         assert!(
-            signature.len() >= need,
+            signature.len() >= self.params.signature_len(),
             "signature too short for LSH parameters"
         );
-
-        // Local counter: id -> collision count
-        let mut counts: HashMap<u32, u32, FxBuildHasher> =
-            HashMap::with_hasher(FxBuildHasher::default());
-
-        for b in 0..self.params.bands {
-            let start = b * self.params.rows_per_band;
-            let end = start + self.params.rows_per_band;
-            let key = hash_band(&signature[start..end], (b as u64) ^ self.seed);
-            if let Some(ids) = self.bands[b].get(&key) {
+        let mut counts = vec![0u32; n_ids];
+        // Last band counted per ID, plus one (0: none yet), as in `tally`.
+        let mut last_band = vec![0u32; n_ids];
+        let mut touched: Vec<I> = Vec::new();
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| {
+                let Some(ids) = self.bucket(b, key) else {
+                    return;
+                };
                 for &id in ids {
-                    *counts.entry(id).or_insert(0) += 1;
+                    let i = usize::try_from(id)
+                        .ok()
+                        .filter(|&i| i < n_ids)
+                        .expect("dense query: every ID must lie in 0..n_ids");
+                    if last_band[i] != b as u32 + 1 {
+                        if counts[i] == 0 {
+                            touched.push(id);
+                        }
+                        counts[i] += 1;
+                        last_band[i] = b as u32 + 1;
+                    }
                 }
-            }
-        }
-
-        let mut out = Vec::with_capacity(counts.len());
-        for (id, c) in counts {
-            if (c as usize) >= min_collisions {
-                out.push((id, c));
-            }
-        }
-        // Optional: sort by decreasing collisions, then id for stability
-        out.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            },
+        );
+        let mut out: Vec<(I, u32)> = touched
+            .into_iter()
+            .filter_map(|id| {
+                let c = counts[usize::try_from(id).ok()?];
+                ((c as usize) >= min_collisions).then_some((id, c))
+            })
+            .collect();
+        self.sort_candidates(&mut out);
         out
     }
+
+    /// Non-panicking `query_candidates`: a signature shorter than
+    /// `bands * rows_per_band` yields `LshError::ShortSignature`. Extra
+    /// values past that prefix are ignored.
+    pub fn query_candidates_checked(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+    ) -> Result<Vec<(I, u32)>, LshError> {
+        self.check_signature(signature)?;
+        Ok(self.query_candidates(signature, min_collisions))
+    }
+
+    /// Allocation-free `query_candidates`: clears and reuses `buf`, returning
+    /// a view of its results (same order as `query_candidates`).
+    pub fn query_candidates_buf<'b>(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+        buf: &'b mut LshQueryBuffer<I>,
+    ) -> &'b [(I, u32)] {
+        self.query_with_counts(signature, min_collisions, None, buf);
+        &buf.out
+    }
+
+    /// Like `query_candidates`, but lists the colliding band indices of each
+    /// candidate (ascending) instead of only counting them, e.g. to require
+    /// collisions in specific bands. Ordered like `query_candidates`.
+    ///
+    /// # Panics
+    /// If the signature is too short, or the index has more than
+    /// `u32::MAX + 1` bands.
+    pub fn query_candidates_detailed(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+    ) -> Vec<(I, Vec<u32>)> {
+        assert!(
+            signature.len() >= self.params.signature_len(),
+            "signature too short for LSH parameters"
+        );
+        assert!(
+            u32::try_from(self.params.bands.saturating_sub(1)).is_ok(),
+            "too many bands for u32 band indices"
+        );
+        let mut hits: HashMap<I, Vec<u32>, FxBuildHasher> = HashMap::with_hasher(FxBuildHasher);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| {
+                if let Some(ids) = self.bucket(b, key) {
+                    for &id in ids {
+                        let bands = hits.entry(id).or_default();
+                        // An ID repeated within a bucket still hits the band once.
+                        if bands.last() != Some(&(b as u32)) {
+                            bands.push(b as u32);
+                        }
+                    }
+                }
+            },
+        );
+        let mut out: Vec<(I, Vec<u32>)> = hits
+            .into_iter()
+            .filter(|(_, bands)| bands.len() >= min_collisions)
+            .collect();
+        out.sort_unstable_by(|a, b| {
+            b.1.len()
+                .cmp(&a.1.len())
+                .then_with(|| self.tie_cmp(&a.0, &b.0))
+        });
+        out
+    }
+
+    /// Whether `a` and `b` hold the same raw row values in `band`, i.e. their
+    /// equal band keys (if any) are a true collision and not two different
+    /// windows folding to the same 64-bit key.
+    ///
+    /// With 64-bit keys a false key collision between two distinct windows
+    /// has probability about 2^-64 per band, so a query against `n` indexed
+    /// signatures expects roughly `bands * n / 2^64` of them: about 1e-9 even
+    /// for a billion signatures and 32 bands. This is mainly a safeguard for
+    /// custom or weak [`Mixer`]s.
+    ///
+    /// # Panics
+    /// If `band >= bands` or either signature is too short.
+    pub fn verify_band_collision(&self, a: &[u64], b: &[u64], band: usize) -> bool {
+        assert!(band < self.params.bands, "band index out of range");
+        let rows = self.params.rows_per_band;
+        let window = band * rows..(band + 1) * rows;
+        a[window.clone()] == b[window]
+    }
+
+    /// `query_candidates` recounting each candidate's collisions over the
+    /// bands that pass `verify_band_collision` against its stored signature,
+    /// so false key collisions cannot lift it over `min_collisions`. Only
+    /// possible for indexes that store signatures; otherwise, and for
+    /// candidates without one, the unverified counts are returned.
+    pub fn query_candidates_verified(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+    ) -> Vec<(I, u32)> {
+        let Some(store) = &self.signatures else {
+            return self.query_candidates(signature, min_collisions);
+        };
+        let mut out: Vec<(I, u32)> = self
+            .query_candidates_detailed(signature, min_collisions)
+            .into_iter()
+            .map(|(id, bands)| {
+                let c = match store.get(&id) {
+                    Some(stored) => bands
+                        .iter()
+                        .filter(|&&b| self.verify_band_collision(signature, stored, b as usize))
+                        .count(),
+                    None => bands.len(),
+                };
+                (id, c as u32)
+            })
+            .filter(|&(_, c)| (c as usize) >= min_collisions)
+            .collect();
+        self.sort_candidates(&mut out);
+        out
+    }
+
+    /// Coarse query scanning only the bands in `band_range`, e.g. half of
+    /// them as a cheap pre-filter before a full query. Collisions are counted
+    /// over those bands only (at most `band_range.len()`); ordered like
+    /// `query_candidates`. The signature only needs
+    /// to cover the rows of the scanned bands.
+    ///
+    /// # Panics
+    /// If `band_range` is decreasing or ends past `bands`, or the signature
+    /// is too short for it.
+    pub fn query_candidates_bands(
+        &self,
+        signature: &[u64],
+        band_range: Range<usize>,
+        min_collisions: usize,
+    ) -> Vec<(I, u32)> {
+        assert!(
+            band_range.start <= band_range.end && band_range.end <= self.params.bands,
+            "band range {band_range:?} out of bounds for {} bands",
+            self.params.bands
+        );
+        assert!(
+            signature.len() >= band_range.end * self.params.rows_per_band,
+            "signature too short for LSH parameters"
+        );
+        let mut counts: Collisions<I> = HashMap::with_hasher(FxBuildHasher);
+        for b in band_range {
+            if let Some(ids) = self.bucket(b, self.band_key(signature, b)) {
+                for &id in ids {
+                    tally(&mut counts, id, b);
+                }
+            }
+        }
+        let mut out: Vec<(I, u32)> = counts
+            .into_iter()
+            .map(|(id, (c, _))| (id, c))
+            .filter(|&(_, c)| (c as usize) >= min_collisions)
+            .collect();
+        self.sort_candidates(&mut out);
+        out
+    }
+
+    /// Query once and split the result by several thresholds: `out[i]` holds
+    /// the candidates with at least `tiers[i]` collisions, ordered like
+    /// `query_candidates`. Tiers are cumulative, not disjoint: for tiers
+    /// `[1, 4, 8]` the last list is a subset of the second, which is a subset
+    /// of the first. `tiers` need not be sorted.
+    pub fn query_tiered(&self, signature: &[u64], tiers: &[usize]) -> Vec<Vec<(I, u32)>> {
+        let Some(&lowest) = tiers.iter().min() else {
+            return Vec::new();
+        };
+        let all = self.query_candidates(signature, lowest);
+        tiers
+            .iter()
+            .map(|&t| {
+                // `all` is sorted by decreasing collisions, so each tier is a prefix.
+                let end = all.partition_point(|&(_, c)| (c as usize) >= t);
+                all[..end].to_vec()
+            })
+            .collect()
+    }
+
+    /// Streaming `query_candidates`: calls `f(id, collisions)` for each
+    /// qualifying candidate without collecting or sorting a result `Vec`.
+    /// Call order is unspecified (collision-map iteration order).
+    pub fn query_candidates_into(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+        mut f: impl FnMut(I, u32),
+    ) {
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        for (id, (c, _)) in counts {
+            if (c as usize) >= min_collisions {
+                f(id, c);
+            }
+        }
+    }
+
+    /// Iterator form of `query_candidates` for `take`/`filter` chains.
+    /// Collision counts are computed eagerly when called; only the filtering
+    /// by `min_collisions` is lazy. Unlike `query_candidates`, results are
+    /// **unsorted** (collision-map iteration order), so taking a prefix does
+    /// not give the best candidates.
+    pub fn candidates_iter<'a>(
+        &'a self,
+        signature: &'a [u64],
+        min_collisions: usize,
+    ) -> impl Iterator<Item = (I, u32)> + 'a {
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        counts
+            .into_iter()
+            .map(|(id, (c, _))| (id, c))
+            .filter(move |&(_, c)| (c as usize) >= min_collisions)
+    }
+
+    /// Like `query_candidates`, but never reports `exclude` (typically the
+    /// query's own ID in all-vs-all runs). Other IDs inserted with the same
+    /// signature are still reported.
+    pub fn query_candidates_excluding(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+        exclude: I,
+    ) -> Vec<(I, u32)> {
+        let mut buf = LshQueryBuffer::new();
+        self.query_with_counts(signature, min_collisions, Some(exclude), &mut buf);
+        buf.out
+    }
+
+    /// Query with the stored signature of `id`. Returns `None` if the index
+    /// does not store signatures or `id` was never inserted.
+    pub fn query_by_id(&self, id: I, min_collisions: usize) -> Option<Vec<(I, u32)>> {
+        let sig = self.signatures.as_ref()?.get(&id)?;
+        Some(self.query_candidates(sig, min_collisions))
+    }
+
+    /// Score `candidates` against `query` with `jaccard_from_signatures` over
+    /// their stored signatures. Candidates without a stored signature (or all
+    /// of them, if the index does not store signatures) are skipped; input
+    /// order is preserved.
+    pub fn refine(&self, query: &[u64], candidates: &[(I, u32)]) -> Vec<(I, f64)> {
+        let Some(store) = &self.signatures else {
+            return Vec::new();
+        };
+        candidates
+            .iter()
+            .filter_map(|&(id, _)| {
+                let sig = store.get(&id)?;
+                Some((id, jaccard_from_signatures(query, sig)))
+            })
+            .collect()
+    }
+
+    /// Like `query_candidates`, with a Jaccard estimate per candidate.
+    ///
+    /// Each band collides with probability `s^r`, so the fraction of colliding
+    /// bands `c / bands` estimates `s^r` and `s ≈ (c / bands)^(1/r)`. This is
+    /// an index-level approximation with only `bands` observations; it is much
+    /// coarser than a full-signature estimate such as `jaccard_from_signatures`.
+    #[cfg(feature = "std")]
+    pub fn query_with_jaccard(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+    ) -> Vec<(I, u32, f64)> {
+        let bands = self.params.bands as f64;
+        let inv_rows = 1.0 / self.params.rows_per_band as f64;
+        self.query_candidates(signature, min_collisions)
+            .into_iter()
+            .map(|(id, c)| (id, c, (c as f64 / bands).powf(inv_rows)))
+            .collect()
+    }
+
+    /// Query where each colliding band `b` adds `weights[b]` to the candidate's
+    /// score instead of 1, e.g. to downweight low-complexity bands. Returns
+    /// `(id, score)` for scores `>= min_score`, by decreasing score (ties: see
+    /// [`TieBreak`]). With all weights 1.0 this ranks like `query_candidates`.
+    ///
+    /// # Panics
+    /// If `weights.len() != bands` or the signature is too short.
+    pub fn query_candidates_weighted(
+        &self,
+        signature: &[u64],
+        weights: &[f64],
+        min_score: f64,
+    ) -> Vec<(I, f64)> {
+        assert_eq!(weights.len(), self.params.bands, "need one weight per band");
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+        // Score plus last scored band, as in `tally`.
+        let mut scores: HashMap<I, (f64, u32), FxBuildHasher> = HashMap::with_hasher(FxBuildHasher);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| {
+                if let Some(ids) = self.bucket(b, key) {
+                    for &id in ids {
+                        let entry = scores.entry(id).or_insert((0.0, u32::MAX));
+                        if entry.1 != b as u32 {
+                            entry.0 += weights[b];
+                            entry.1 = b as u32;
+                        }
+                    }
+                }
+            },
+        );
+        let mut out: Vec<(I, f64)> = scores
+            .into_iter()
+            .map(|(id, (score, _))| (id, score))
+            .filter(|&(_, score)| score >= min_score)
+            .collect();
+        out.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then_with(|| self.tie_cmp(&a.0, &b.0)));
+        out
+    }
+
+    /// The first `k` candidates of `query_candidates(signature, 1)`: the
+    /// most collisions, ties broken by the index's [`TieBreak`].
+    ///
+    /// Keeps the best `k` in a bounded heap while scanning the collision
+    /// counts, so only `k` candidates are ever collected and sorted.
+    pub fn query_top_k(&self, signature: &[u64], k: usize) -> Vec<(I, u32)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        self.count_collisions(signature, None, &mut counts);
+        // Max-heap on rank, so the top is the worst of the kept candidates.
+        let mut heap: BinaryHeap<Ranked<'_, I>> = BinaryHeap::with_capacity(k.min(counts.len()));
+        for (id, (c, _)) in counts {
+            let cand = Ranked {
+                id,
+                collisions: c,
+                tie_break: self.tie_break,
+                ids: &self.ids,
+            };
+            if heap.len() < k {
+                heap.push(cand);
+            } else if let Some(mut worst) = heap.peek_mut() {
+                if cand < *worst {
+                    *worst = cand;
+                }
+            }
+        }
+        let mut out: Vec<(I, u32)> = heap.into_iter().map(|r| (r.id, r.collisions)).collect();
+        self.sort_candidates(&mut out);
+        out
+    }
+
+    /// Multi-probe query: besides each band's own bucket, look up `probes`
+    /// perturbed keys of that band, to recover near-misses without adding
+    /// bands. Probes are taken in this order until `probes` are used:
+    ///
+    /// 1. Row substitution: for each row of the band, in order, the key of
+    ///    the band with that row replaced by the band's second-smallest row
+    ///    value (rows already holding that value are skipped; single-row
+    ///    bands have none).
+    /// 2. Bit flips: the band's folded key XORed with 1, 2, 3, ..., i.e. with
+    ///    every pattern of its low-order bits.
+    ///
+    /// Each band still adds at most one collision per candidate, so counts
+    /// never exceed `bands`, and `probes == 0` is exactly `query_candidates`.
+    /// Buckets above `max_bucket_size` are skipped as in every query.
+    ///
+    /// Recall/latency tradeoff: a query costs `bands * (1 + probes)` bucket
+    /// lookups instead of `bands`, and the candidate set grows with every
+    /// probe. Substitution probes find entries whose band differs from the
+    /// query in exactly that row. Band keys are well-mixed hashes, so a
+    /// flipped key's bucket holds no more similar entries than any other:
+    /// flip probes add candidates at random. Keep `probes` small and verify
+    /// candidates, e.g. with `refine`.
+    pub fn query_candidates_multiprobe(
+        &self,
+        signature: &[u64],
+        min_collisions: usize,
+        probes: usize,
+    ) -> Vec<(I, u32)> {
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+        let rows = self.params.rows_per_band;
+        let mut counts: Collisions<I> = HashMap::with_hasher(FxBuildHasher);
+        let mut window: SmallVec<[u64; 16]> = SmallVec::with_capacity(rows);
+        for b in 0..self.params.bands {
+            let probe = |counts: &mut Collisions<I>, key: u64| {
+                for &id in self.bucket(b, key).into_iter().flatten() {
+                    tally(counts, id, b);
+                }
+            };
+            let band = &signature[b * rows..(b + 1) * rows];
+            let key = self.band_key(signature, b);
+            probe(&mut counts, key);
+
+            let mut left = probes;
+            if rows > 1 {
+                let second = second_smallest(band);
+                for i in 0..rows {
+                    if left == 0 {
+                        break;
+                    }
+                    if band[i] == second {
+                        continue;
+                    }
+                    window.clear();
+                    window.extend_from_slice(band);
+                    window[i] = second;
+                    let key = window_key(&window, self.seed, self.band_hasher, &self.mixer, b);
+                    probe(&mut counts, key);
+                    left -= 1;
+                }
+            }
+            for mask in 1..=left as u64 {
+                probe(&mut counts, key ^ mask);
+            }
+        }
+        let mut out: Vec<(I, u32)> = counts
+            .into_iter()
+            .filter(|&(_, (c, _))| (c as usize) >= min_collisions)
+            .map(|(id, (c, _))| (id, c))
+            .collect();
+        self.sort_candidates(&mut out);
+        out
+    }
+
+    /// Every colliding pair once, as `(id_lo, id_hi, collisions)` with
+    /// `id_lo < id_hi`, sorted by decreasing collisions then by IDs.
+    ///
+    /// Cost is O(bucket_size^2) per bucket, so a few huge buckets dominate;
+    /// buckets above `max_bucket_size` are skipped, see also
+    /// `candidate_pairs_capped`.
+    pub fn candidate_pairs(&self, min_collisions: usize) -> Vec<(I, I, u32)> {
+        self.candidate_pairs_capped(min_collisions, self.max_bucket_size.unwrap_or(usize::MAX))
+    }
+
+    /// Like `candidate_pairs`, but buckets holding more than `max_bucket_size`
+    /// IDs are ignored as uninformative.
+    pub fn candidate_pairs_capped(
+        &self,
+        min_collisions: usize,
+        max_bucket_size: usize,
+    ) -> Vec<(I, I, u32)> {
+        let mut counts: HashMap<(I, I), u32, FxBuildHasher> = HashMap::with_hasher(FxBuildHasher);
+        let mut members: Vec<I> = Vec::new();
+        for map in &self.bands {
+            for ids in map.values() {
+                if ids.len() < 2 || ids.len() > max_bucket_size {
+                    continue;
+                }
+                members.clear();
+                members.extend_from_slice(ids);
+                members.sort_unstable();
+                members.dedup();
+                for (i, &lo) in members.iter().enumerate() {
+                    for &hi in &members[i + 1..] {
+                        *counts.entry((lo, hi)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        let mut out: Vec<(I, I, u32)> = counts
+            .into_iter()
+            .filter(|&(_, c)| (c as usize) >= min_collisions)
+            .map(|((lo, hi), c)| (lo, hi, c))
+            .collect();
+        out.sort_unstable_by(|a, b| {
+            b.2.cmp(&a.2)
+                .then_with(|| a.0.cmp(&b.0))
+                .then_with(|| a.1.cmp(&b.1))
+        });
+        out
+    }
+
+    /// Distribution of collision counts over a batch of queries:
+    /// `hist[c]` is the number of (query, candidate) pairs with exactly `c`
+    /// band collisions, for `c` in `0..=bands`. Pairs that never collide are
+    /// not candidates, so `hist[0]` is always 0. Useful for checking where a
+    /// `min_collisions` threshold falls on known-similar pairs.
+    ///
+    /// # Panics
+    /// If any signature is too short.
+    pub fn collision_histogram(&self, signatures: &[Vec<u64>]) -> Vec<u64> {
+        let mut hist = vec![0u64; self.params.bands + 1];
+        let mut counts = HashMap::with_hasher(FxBuildHasher);
+        for sig in signatures {
+            self.count_collisions(sig, None, &mut counts);
+            for &(c, _) in counts.values() {
+                hist[c as usize] += 1;
+            }
+        }
+        hist
+    }
+
+    /// Query many signatures, reusing one query buffer across queries.
+    /// Each result is ordered exactly as `query_candidates` orders it.
+    pub fn query_candidates_batch(
+        &self,
+        signatures: &[Vec<u64>],
+        min_collisions: usize,
+    ) -> Vec<Vec<(I, u32)>> {
+        let mut buf = LshQueryBuffer::new();
+        signatures
+            .iter()
+            .map(|sig| {
+                self.query_with_counts(sig, min_collisions, None, &mut buf);
+                core::mem::take(&mut buf.out)
+            })
+            .collect()
+    }
+
+    /// Parallel `query_candidates_batch`: queries are mapped independently on
+    /// rayon's global pool, each worker reusing its own query buffer.
+    #[cfg(feature = "parallel")]
+    pub fn query_candidates_batch_parallel(
+        &self,
+        signatures: &[Vec<u64>],
+        min_collisions: usize,
+    ) -> Vec<Vec<(I, u32)>>
+    where
+        I: Send + Sync,
+        M: Sync,
+    {
+        signatures
+            .par_iter()
+            .map_init(LshQueryBuffer::new, |buf, sig| {
+                self.query_with_counts(sig, min_collisions, None, buf);
+                core::mem::take(&mut buf.out)
+            })
+            .collect()
+    }
+
+    /// Core query loop; both halves of `buf` are cleared first and left
+    /// holding this query's tallies and sorted results.
+    fn query_with_counts<R: Row>(
+        &self,
+        signature: &[R],
+        min_collisions: usize,
+        exclude: Option<I>,
+        buf: &mut LshQueryBuffer<I>,
+    ) {
+        self.count_collisions(signature, exclude, &mut buf.counts);
+
+        let out = &mut buf.out;
+        out.clear();
+        for (&id, &(c, _)) in buf.counts.iter() {
+            if (c as usize) >= min_collisions {
+                out.push((id, c));
+            }
+        }
+        self.sort_candidates(out);
+    }
+
+    /// Sort by decreasing collisions, then by the tie-break rule for stability.
+    fn sort_candidates(&self, out: &mut [(I, u32)]) {
+        sort_by_tie_break(out, self.tie_break, &self.ids);
+    }
+
+    /// Order of two equally ranked candidates under the tie-break rule.
+    #[inline]
+    fn tie_cmp(&self, a: &I, b: &I) -> core::cmp::Ordering {
+        tie_order(self.tie_break, &self.ids, a, b)
+    }
+
+    /// Bucket lookup for queries, honoring `max_bucket_size`.
+    #[inline]
+    fn bucket(&self, b: usize, key: u64) -> Option<&[I]> {
+        let ids = self.bands[b].get(&key)?;
+        if self.max_bucket_size.is_some_and(|max| ids.len() > max) {
+            self.skipped_buckets.bump();
+            return None;
+        }
+        Some(ids)
+    }
+
+    /// Band scan shared by all query variants: clears `counts`, then tallies
+    /// band collisions per ID.
+    fn count_collisions<R: Row>(
+        &self,
+        signature: &[R],
+        exclude: Option<I>,
+        counts: &mut Collisions<I>,
+    ) {
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+
+        // Local counter: id -> collision count
+        counts.clear();
+
+        // Two passes: resolve every band's bucket first, then tally. The
+        // probes are independent of each other and of `counts`, so on indexes
+        // much larger than cache the CPU overlaps their misses instead of
+        // stalling on each one between tallies.
+        let mut buckets: SmallVec<[Option<&[I]>; 64]> = SmallVec::with_capacity(self.params.bands);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| buckets.push(self.bucket(b, key)),
+        );
+        for (b, ids) in buckets.into_iter().enumerate() {
+            for &id in ids.into_iter().flatten() {
+                if Some(id) != exclude {
+                    tally(counts, id, b);
+                }
+            }
+        }
+    }
+}
+
+/// Per-ID `(collisions, last band counted)` built up during a query.
+type Collisions<I> = HashMap<I, (u32, u32), FxBuildHasher>;
+
+/// Count a collision of `id` in band `b`. Bands are scanned in order, so
+/// remembering the last counted band makes an ID repeated within one bucket
+/// count once: each band adds at most one collision, and no count can exceed
+/// `bands`.
+#[inline]
+fn tally<I: IndexId>(counts: &mut Collisions<I>, id: I, b: usize) {
+    let entry = counts.entry(id).or_insert((0, u32::MAX));
+    if entry.1 != b as u32 {
+        entry.0 += 1;
+        entry.1 = b as u32;
+    }
+}
+
+/// Folded key of band `b` for `signature` (length already validated).
+#[inline]
+fn band_key<R: Row>(
+    signature: &[R],
+    rows_per_band: usize,
+    seed: u64,
+    hasher: BandHasher,
+    mixer: &impl Mixer,
+    b: usize,
+) -> u64 {
+    let start = b * rows_per_band;
+    let end = start + rows_per_band;
+    window_key(&signature[start..end], seed, hasher, mixer, b)
+}
+
+/// Key of `window` when looked up in band `b`.
+#[inline]
+fn window_key<R: Row>(
+    window: &[R],
+    seed: u64,
+    hasher: BandHasher,
+    mixer: &impl Mixer,
+    b: usize,
+) -> u64 {
+    match hasher {
+        BandHasher::SplitMix => R::fold(mixer, window, band_seed(seed, b)),
+        #[cfg(feature = "sip")]
+        BandHasher::Sip => {
+            use core::hash::Hasher;
+            let mut h = siphasher::sip::SipHasher24::new_with_keys(seed, splitmix64(seed));
+            // Little-endian bytes keep keys identical across host byte order.
+            h.write(&(b as u64).to_le_bytes());
+            for &v in window {
+                h.write(&v.widen().to_le_bytes());
+            }
+            h.finish()
+        }
+    }
+}
+
+/// Calls `f(b, key)` for every band in order, folding four bands at a time
+/// with `Mixer::fold_x4` for `BandHasher::SplitMix`. Keys equal
+/// `band_key(.., b)` exactly.
+#[inline]
+fn for_each_band_key<R: Row>(
+    signature: &[R],
+    params: &LshParams,
+    seed: u64,
+    hasher: BandHasher,
+    mixer: &impl Mixer,
+    mut f: impl FnMut(usize, u64),
+) {
+    let rows = params.rows_per_band;
+    let chunk = |b: usize| &signature[b * rows..(b + 1) * rows];
+    let mut b = 0;
+    while hasher == BandHasher::SplitMix && b + 4 <= params.bands {
+        let keys = R::fold_x4(
+            mixer,
+            [chunk(b), chunk(b + 1), chunk(b + 2), chunk(b + 3)],
+            [
+                band_seed(seed, b),
+                band_seed(seed, b + 1),
+                band_seed(seed, b + 2),
+                band_seed(seed, b + 3),
+            ],
+        );
+        for (i, key) in keys.into_iter().enumerate() {
+            f(b + i, key);
+        }
+        b += 4;
+    }
+    for b in b..params.bands {
+        f(b, band_key(signature, rows, seed, hasher, mixer, b));
+    }
+}
+
+/// Signature slot type accepted by band folding: `u64`, or `u32` folded as
+/// its zero-extension, so both give identical band keys.
+trait Row: Copy {
+    fn widen(self) -> u64;
+
+    fn fold(mixer: &impl Mixer, chunk: &[Self], seed: u64) -> u64;
+
+    #[inline]
+    fn fold_x4(mixer: &impl Mixer, chunks: [&[Self]; 4], seeds: [u64; 4]) -> [u64; 4] {
+        [0, 1, 2, 3].map(|i| Self::fold(mixer, chunks[i], seeds[i]))
+    }
+}
+
+impl Row for u64 {
+    #[inline]
+    fn widen(self) -> u64 {
+        self
+    }
+
+    #[inline]
+    fn fold(mixer: &impl Mixer, chunk: &[u64], seed: u64) -> u64 {
+        mixer.fold(chunk, seed)
+    }
+
+    #[inline]
+    fn fold_x4(mixer: &impl Mixer, chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+        mixer.fold_x4(chunks, seeds)
+    }
+}
+
+impl Row for u32 {
+    #[inline]
+    fn widen(self) -> u64 {
+        u64::from(self)
+    }
+
+    #[inline]
+    fn fold(mixer: &impl Mixer, chunk: &[u32], seed: u64) -> u64 {
+        mixer.fold_u32(chunk, seed)
+    }
+}
+
+/// Second element of `rows` in ascending order (equal to the minimum if it
+/// repeats); `rows` holds at least two values.
+fn second_smallest(rows: &[u64]) -> u64 {
+    let (mut lo, mut hi) = (u64::MAX, u64::MAX);
+    for &v in rows {
+        if v < lo {
+            hi = lo;
+            lo = v;
+        } else if v < hi {
+            hi = v;
+        }
+    }
+    hi
+}
+
+/// Effective seed of band `b`: both the base seed and the band index go
+/// through splitmix64, so band seeds are statistically independent rather
+/// than differing in a few low bits.
+#[inline]
+fn band_seed(seed: u64, b: usize) -> u64 {
+    splitmix64(seed ^ splitmix64(b as u64))
+}
+
+/// Add `n` live inserts of `id`, giving it the next sequence number if new.
+fn note_id<I: IndexId>(
+    ids: &mut HashMap<I, IdEntry, FxBuildHasher>,
+    next_seq: &mut u64,
+    id: I,
+    n: u32,
+) {
+    let entry = ids.entry(id).or_insert_with(|| {
+        *next_seq += 1;
+        IdEntry {
+            count: 0,
+            seq: *next_seq - 1,
+        }
+    });
+    entry.count += n;
+}
+
+/// Order of equally ranked `a` and `b` under `tie_break`. For
+/// `InsertionOrder`, IDs missing from `ids` sort last.
+#[inline]
+fn tie_order<I: IndexId>(
+    tie_break: TieBreak,
+    ids: &HashMap<I, IdEntry, FxBuildHasher>,
+    a: &I,
+    b: &I,
+) -> core::cmp::Ordering {
+    match tie_break {
+        TieBreak::LowerId => a.cmp(b),
+        TieBreak::HigherId => b.cmp(a),
+        TieBreak::InsertionOrder => {
+            let seq = |id: &I| ids.get(id).map_or(u64::MAX, |e| e.seq);
+            seq(a).cmp(&seq(b))
+        }
+    }
+}
+
+/// Candidate ordered by rank: `Less` means more collisions, or an earlier
+/// place under the tie-break rule. Lets `query_top_k` keep its best `k` in a
+/// `BinaryHeap`.
+struct Ranked<'a, I> {
+    id: I,
+    collisions: u32,
+    tie_break: TieBreak,
+    ids: &'a HashMap<I, IdEntry, FxBuildHasher>,
+}
+
+impl<I: IndexId> Ord for Ranked<'_, I> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        other
+            .collisions
+            .cmp(&self.collisions)
+            .then_with(|| tie_order(self.tie_break, self.ids, &self.id, &other.id))
+    }
+}
+
+impl<I: IndexId> PartialOrd for Ranked<'_, I> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<I: IndexId> PartialEq for Ranked<'_, I> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl<I: IndexId> Eq for Ranked<'_, I> {}
+
+/// Sort candidates by decreasing collisions, then by `tie_order`; shared by
+/// `LshIndex` and `CompactLshIndex` so both rank identically.
+fn sort_by_tie_break<I: IndexId>(
+    out: &mut [(I, u32)],
+    tie_break: TieBreak,
+    ids: &HashMap<I, IdEntry, FxBuildHasher>,
+) {
+    out.sort_unstable_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| tie_order(tie_break, ids, &a.0, &b.0))
+    });
+}
+
+/// Compact index produced by [`LshIndex::compile`].
+///
+/// Each band stores its bucket keys sorted in one array, with the IDs of all
+/// buckets concatenated into one arena, so lookups are a binary search over
+/// contiguous memory and the whole index holds O(bands) allocations rather
+/// than one per bucket. Queries return exactly what the source index would,
+/// including its [`TieBreak`] order.
+///
+/// Late `insert`s go to a small per-band overflow map instead of the arena;
+/// see [`insert`](Self::insert) and [`recompile`](Self::recompile).
+pub struct CompactLshIndex<I = u32, M = SplitMix64> {
+    params: LshParams,
+    seed: u64,
+    band_hasher: BandHasher,
+    max_bucket_size: Option<usize>,
+    bands: Vec<CompactBand<I>>,
+    // Per band, buckets of signatures inserted since the last (re)compile.
+    overflow: Vec<HashMap<u64, Vec<I>, FxBuildHasher>>,
+    // Signatures in `overflow`, and the count that triggers `recompile`.
+    overflow_len: usize,
+    overflow_limit: usize,
+    len: usize,
+    // Carried over from the source index so queries rank identically.
+    tie_break: TieBreak,
+    ids: HashMap<I, IdEntry, FxBuildHasher>,
+    next_seq: u64,
+    mixer: M,
+}
+
+/// Default `CompactLshIndex::overflow_limit`.
+const DEFAULT_OVERFLOW_LIMIT: usize = 4096;
+
+struct CompactBand<I> {
+    // Sorted; bucket `i` holds `ids[offsets[i]..offsets[i + 1]]`.
+    keys: Vec<u64>,
+    offsets: Vec<usize>,
+    ids: Vec<I>,
+}
+
+impl<I: Copy> CompactBand<I> {
+    /// Arena over `buckets`, which must be sorted by key.
+    fn from_sorted(buckets: Vec<(u64, Vec<I>)>) -> Self {
+        let mut band = CompactBand {
+            keys: Vec::with_capacity(buckets.len()),
+            offsets: Vec::with_capacity(buckets.len() + 1),
+            ids: Vec::with_capacity(buckets.iter().map(|(_, ids)| ids.len()).sum()),
+        };
+        band.offsets.push(0);
+        for (key, ids) in buckets {
+            band.push(key, &ids);
+        }
+        band
+    }
+
+    #[inline]
+    fn push(&mut self, key: u64, ids: &[I]) {
+        self.keys.push(key);
+        self.ids.extend_from_slice(ids);
+        self.offsets.push(self.ids.len());
+    }
+
+    #[inline]
+    fn get(&self, key: u64) -> Option<&[I]> {
+        let i = self.keys.binary_search(&key).ok()?;
+        Some(&self.ids[self.offsets[i]..self.offsets[i + 1]])
+    }
+
+    /// Merge `extra` buckets into the arena, appending their IDs after the
+    /// arena's own for shared keys.
+    fn absorb(&mut self, extra: HashMap<u64, Vec<I>, FxBuildHasher>) {
+        if extra.is_empty() {
+            return;
+        }
+        let mut extra: Vec<(u64, Vec<I>)> = extra.into_iter().collect();
+        extra.sort_unstable_by_key(|&(key, _)| key);
+        let added: usize = extra.iter().map(|(_, ids)| ids.len()).sum();
+        let mut out = CompactBand {
+            keys: Vec::with_capacity(self.keys.len() + extra.len()),
+            offsets: Vec::with_capacity(self.offsets.len() + extra.len()),
+            ids: Vec::with_capacity(self.ids.len() + added),
+        };
+        out.offsets.push(0);
+        let mut extra = extra.into_iter().peekable();
+        for (i, &key) in self.keys.iter().enumerate() {
+            while let Some((k, ids)) = extra.next_if(|&(k, _)| k < key) {
+                out.push(k, &ids);
+            }
+            out.keys.push(key);
+            out.ids
+                .extend_from_slice(&self.ids[self.offsets[i]..self.offsets[i + 1]]);
+            if let Some((_, ids)) = extra.next_if(|&(k, _)| k == key) {
+                out.ids.extend(ids);
+            }
+            out.offsets.push(out.ids.len());
+        }
+        for (key, ids) in extra {
+            out.push(key, &ids);
+        }
+        *self = out;
+    }
+}
+
+impl<I: IndexId, M: Mixer> CompactLshIndex<I, M> {
+    /// Same as `LshIndex::query_candidates` on the index this was compiled
+    /// from (plus any later `insert`s).
+    pub fn query_candidates(&self, signature: &[u64], min_collisions: usize) -> Vec<(I, u32)> {
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+        let mut counts: Collisions<I> = HashMap::with_hasher(FxBuildHasher);
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| {
+                let ids = self.bands[b].get(key).unwrap_or(&[]);
+                let late: &[I] = match self.overflow_len {
+                    0 => &[],
+                    _ => self.overflow[b].get(&key).map_or(&[], Vec::as_slice),
+                };
+                if self
+                    .max_bucket_size
+                    .is_some_and(|max| ids.len() + late.len() > max)
+                {
+                    return;
+                }
+                for &id in ids.iter().chain(late) {
+                    tally(&mut counts, id, b);
+                }
+            },
+        );
+        let mut out: Vec<(I, u32)> = counts
+            .into_iter()
+            .map(|(id, (c, _))| (id, c))
+            .filter(|&(_, c)| (c as usize) >= min_collisions)
+            .collect();
+        sort_by_tie_break(&mut out, self.tie_break, &self.ids);
+        out
+    }
+
+    /// Add a signature after compilation. It lands in a per-band overflow
+    /// map that queries look up alongside the arena, costing one extra hash
+    /// lookup per band while the overflow is non-empty. When more than
+    /// `overflow_limit` signatures have accumulated, the overflow is folded
+    /// into the arena (`recompile`), which rewrites every band: O(total IDs).
+    pub fn insert(&mut self, id: I, signature: &[u64]) -> Result<(), LshError> {
+        let need = self.params.signature_len();
+        if signature.len() < need {
+            return Err(LshError::ShortSignature {
+                sig_len: signature.len(),
+                need,
+            });
+        }
+        let overflow = &mut self.overflow;
+        for_each_band_key(
+            signature,
+            &self.params,
+            self.seed,
+            self.band_hasher,
+            &self.mixer,
+            |b, key| overflow[b].entry(key).or_default().push(id),
+        );
+        note_id(&mut self.ids, &mut self.next_seq, id, 1);
+        self.len += 1;
+        self.overflow_len += 1;
+        if self.overflow_len > self.overflow_limit {
+            self.recompile();
+        }
+        Ok(())
+    }
+
+    /// Fold the overflow of late inserts into the arena, restoring pure
+    /// arena lookups. Query results are unchanged.
+    pub fn recompile(&mut self) {
+        for (band, extra) in self.bands.iter_mut().zip(&mut self.overflow) {
+            band.absorb(core::mem::take(extra));
+        }
+        self.overflow_len = 0;
+    }
+
+    /// Order of equal-collision candidates; compiled from the source index,
+    /// see `LshIndex::set_tie_break`.
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    #[inline]
+    pub fn tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// Signatures inserted since the last (re)compile.
+    #[inline]
+    pub fn overflow_len(&self) -> usize {
+        self.overflow_len
+    }
+
+    /// Overflow size above which `insert` recompiles (default 4096).
+    pub fn set_overflow_limit(&mut self, limit: usize) {
+        self.overflow_limit = limit;
+    }
+
+    #[inline]
+    pub fn overflow_limit(&self) -> usize {
+        self.overflow_limit
+    }
+
+    /// Number of signatures compiled in or inserted since.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+}
+
+/// Several LSH tables over the same signatures, queried together to raise
+/// recall beyond what one band configuration reaches.
+///
+/// Reseeding a table alone would not help: band keys change but the same
+/// rows still collide. Each table therefore bands its own fixed pseudo-random
+/// permutation of the first `bands * rows_per_band` signature positions (and
+/// uses its own index seed), so rows are grouped differently per table. The
+/// tables are plain `LshIndex`es; this type only permutes and aggregates.
+///
+/// Memory and insert/query time grow linearly with the number of tables.
+/// Recall rises with it too, though more slowly than for fully independent
+/// tables, since all tables see the same signature values.
+pub struct LshEnsemble<I = u32> {
+    params: LshParams,
+    tables: Vec<LshIndex<I>>,
+    perms: Vec<Vec<usize>>,
+}
+
+impl<I: IndexId> LshEnsemble<I> {
+    /// `num_tables` tables, each with `params`, seeded from `seed`.
+    ///
+    /// Table seeds are drawn from `SplitMix64Rng::new(seed)`, so even table 0,
+    /// which bands the signature in its own order, uses the derived seed
+    /// `splitmix64(seed)` rather than `seed` itself: it buckets like
+    /// `LshIndex::with_params_and_seed(params, splitmix64(seed))`.
+    ///
+    /// # Panics
+    /// If `num_tables == 0`.
+    pub fn new(num_tables: usize, params: LshParams, seed: u64) -> Self {
+        assert!(num_tables > 0, "an ensemble needs at least one table");
+        let len = params.signature_len();
+        let mut rng = SplitMix64Rng::new(seed);
+        let mut tables = Vec::with_capacity(num_tables);
+        let mut perms = Vec::with_capacity(num_tables);
+        for t in 0..num_tables {
+            // Table 0 keeps the identity order, so it matches a plain index.
+            let mut perm: Vec<usize> = (0..len).collect();
+            if t > 0 {
+                // Fisher-Yates with unbiased draws: every permutation is
+                // equally likely.
+                for i in (1..len).rev() {
+                    perm.swap(i, bounded_draw(&mut rng, i as u64 + 1) as usize);
+                }
+            }
+            perms.push(perm);
+            tables.push(LshIndex::with_params_and_seed(
+                params.clone(),
+                rng.next_u64(),
+            ));
+        }
+        Self {
+            params,
+            tables,
+            perms,
+        }
+    }
+
+    #[inline]
+    pub fn num_tables(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// Order of equal-collision candidates in `query_candidates`, as for
+    /// `LshIndex::set_tie_break`. Defaults to `TieBreak::LowerId`.
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        for table in &mut self.tables {
+            table.set_tie_break(tie_break);
+        }
+    }
+
+    #[inline]
+    pub fn tie_break(&self) -> TieBreak {
+        self.tables[0].tie_break()
+    }
+
+    /// Parameters shared by every table.
+    #[inline]
+    pub fn params(&self) -> &LshParams {
+        &self.params
+    }
+
+    /// Insert `signature` into every table.
+    pub fn insert(&mut self, id: I, signature: &[u64]) -> Result<(), LshError> {
+        self.tables[0].check_signature(signature)?;
+        let mut permuted = Vec::with_capacity(self.params.signature_len());
+        for (table, perm) in self.tables.iter_mut().zip(&self.perms) {
+            permute_into(signature, perm, &mut permuted);
+            table.insert(id, &permuted)?;
+        }
+        Ok(())
+    }
+
+    pub fn build(&mut self) {
+        for table in &mut self.tables {
+            table.build();
+        }
+    }
+
+    /// Candidates from all tables, with collisions summed over tables (at
+    /// most `num_tables * bands`); ordered like `LshIndex::query_candidates`.
+    pub fn query_candidates(&self, signature: &[u64], min_collisions: usize) -> Vec<(I, u32)> {
+        let need = self.params.signature_len();
+        assert!(
+            signature.len() >= need,
+            "signature too short for LSH parameters"
+        );
+        let mut totals: HashMap<I, u32, FxBuildHasher> = HashMap::with_hasher(FxBuildHasher);
+        let mut permuted = Vec::with_capacity(need);
+        for (table, perm) in self.tables.iter().zip(&self.perms) {
+            permute_into(signature, perm, &mut permuted);
+            table.query_candidates_into(&permuted, 1, |id, c| {
+                *totals.entry(id).or_insert(0) += c;
+            });
+        }
+        let mut out: Vec<(I, u32)> = totals
+            .into_iter()
+            .filter(|&(_, c)| (c as usize) >= min_collisions)
+            .collect();
+        self.tables[0].sort_candidates(&mut out);
+        out
+    }
+}
+
+/// Uniform draw from `0..n` (`n > 0`) by Lemire's multiply-shift with
+/// rejection, avoiding the bias of `next_u64() % n`.
+fn bounded_draw(rng: &mut SplitMix64Rng, n: u64) -> u64 {
+    let mut m = u128::from(rng.next_u64()) * u128::from(n);
+    if (m as u64) < n {
+        // Reject the low products that would over-represent some outputs.
+        let threshold = n.wrapping_neg() % n;
+        while (m as u64) < threshold {
+            m = u128::from(rng.next_u64()) * u128::from(n);
+        }
+    }
+    (m >> 64) as u64
+}
+
+/// `out[i] = signature[perm[i]]`: the banded prefix in table order.
+fn permute_into(signature: &[u64], perm: &[usize], out: &mut Vec<u64>) {
+    out.clear();
+    out.extend(perm.iter().map(|&p| signature[p]));
+}
+
+/// Greedy incremental clustering in input order, CD-HIT style.
+///
+/// Each signature queries an index of the representatives chosen so far;
+/// candidates with at least `min_collisions` band collisions are verified in
+/// query order (most collisions first) with `jaccard_from_signatures`, and
+/// the signature joins the cluster of the first representative reaching
+/// `jaccard_threshold`. Otherwise it becomes a new representative and is
+/// inserted. Returns clusters in creation order, each led by its
+/// representative followed by members in input order.
+///
+/// # Panics
+/// If a signature is shorter than `params.signature_len()`.
+pub fn greedy_cluster<I: IndexId>(
+    signatures: &[(I, Vec<u64>)],
+    params: LshParams,
+    min_collisions: usize,
+    jaccard_threshold: f64,
+) -> Vec<Vec<I>> {
+    let mut index = LshIndex::with_params_storing(params);
+    let mut cluster_of: HashMap<I, usize, FxBuildHasher> = HashMap::with_hasher(FxBuildHasher);
+    let mut clusters: Vec<Vec<I>> = Vec::new();
+    for (id, sig) in signatures {
+        let candidates = index.query_candidates(sig, min_collisions);
+        let rep = index
+            .refine(sig, &candidates)
+            .into_iter()
+            .find(|&(_, j)| j >= jaccard_threshold);
+        match rep {
+            Some((rep, _)) => clusters[cluster_of[&rep]].push(*id),
+            None => {
+                index
+                    .insert(*id, sig)
+                    .expect("signature too short for LSH parameters");
+                cluster_of.insert(*id, clusters.len());
+                clusters.push(vec![*id]);
+            }
+        }
+    }
+    clusters
+}
+
+#[cfg(feature = "serde")]
+mod serde_impl {
+    //! Serialized form: params, seed and, per band, buckets as a key-sorted
+    //! `Vec<(key, ids)>` so the output is deterministic and diff-friendly.
+
+    use super::{BandHasher, IndexId, LshIndex, LshParams, TieBreak};
+    use crate::util::Mixer;
+    use alloc::format;
+    use alloc::vec::Vec;
+    use hashbrown::HashMap;
+    use rustc_hash::FxBuildHasher;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(bound(deserialize = "I: Deserialize<'de>"))]
+    struct LshIndexRepr<I> {
+        params: LshParams,
+        seed: u64,
+        bands: Vec<Vec<(u64, Vec<I>)>>,
+        /// Stored signatures sorted by ID, present for `with_params_storing` indexes.
+        #[serde(default)]
+        signatures: Option<Vec<(I, Vec<u64>)>>,
+        #[serde(default)]
+        max_bucket_size: Option<usize>,
+        #[serde(default)]
+        band_hasher: BandHasher,
+        #[serde(default)]
+        tie_break: TieBreak,
+        /// Live IDs by first insertion, for `TieBreak::InsertionOrder`.
+        #[serde(default)]
+        insertion_order: Option<Vec<I>>,
+        #[serde(default)]
+        strict: bool,
+    }
+
+    impl<I: IndexId + Serialize, M: Mixer> Serialize for LshIndex<I, M> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let bands = self
+                .bands
+                .iter()
+                .map(|map| {
+                    let mut buckets: Vec<(u64, Vec<I>)> =
+                        map.iter().map(|(&k, v)| (k, v.clone())).collect();
+                    buckets.sort_unstable_by_key(|(k, _)| *k);
+                    buckets
+                })
+                .collect();
+            let signatures = self.signatures.as_ref().map(|store| {
+                let mut sigs: Vec<(I, Vec<u64>)> =
+                    store.iter().map(|(&id, s)| (id, s.to_vec())).collect();
+                sigs.sort_unstable_by_key(|(id, _)| *id);
+                sigs
+            });
+            let mut order: Vec<(u64, I)> = self.ids.iter().map(|(&id, e)| (e.seq, id)).collect();
+            order.sort_unstable();
+            LshIndexRepr {
+                params: self.params.clone(),
+                seed: self.seed,
+                bands,
+                signatures,
+                max_bucket_size: self.max_bucket_size,
+                band_hasher: self.band_hasher,
+                tie_break: self.tie_break,
+                insertion_order: Some(order.into_iter().map(|(_, id)| id).collect()),
+                strict: self.strict,
+            }
+            .serialize(serializer)
+        }
+    }
+
+    /// The mixer is not serialized: a custom one is rebuilt with `Default`.
+    impl<'de, I: IndexId + Deserialize<'de>, M: Mixer + Default> Deserialize<'de> for LshIndex<I, M> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let repr = LshIndexRepr::<I>::deserialize(deserializer)?;
+            let params = LshParams::new(repr.params.bands, repr.params.rows_per_band)
+                .map_err(D::Error::custom)?;
+            if repr.bands.len() != params.bands {
+                return Err(D::Error::custom(format!(
+                    "expected {} bands, found {}",
+                    params.bands,
+                    repr.bands.len()
+                )));
+            }
+            let mut index = LshIndex::with_mixer(params, repr.seed, M::default());
+            index.max_bucket_size = repr.max_bucket_size;
+            index.band_hasher = repr.band_hasher;
+            index.tie_break = repr.tie_break;
+            index.strict = repr.strict;
+            // Assign sequence numbers first; IDs missing from the recorded
+            // order then follow in band-0 order.
+            for id in repr.insertion_order.into_iter().flatten() {
+                index.note_inserted(id, 0);
+            }
+            for (_, ids) in &repr.bands[0] {
+                index.len += ids.len();
+                for &id in ids {
+                    index.note_inserted(id, 1);
+                }
+            }
+            // Drop recorded IDs that have no bucket entry.
+            index.ids.retain(|_, e| e.count > 0);
+            for (map, buckets) in index.bands.iter_mut().zip(repr.bands) {
+                let mut restored = HashMap::with_capacity_and_hasher(buckets.len(), FxBuildHasher);
+                restored.extend(buckets);
+                *map = restored;
+            }
+            index.signatures = repr.signatures.map(|sigs| sigs.into_iter().collect());
+            Ok(index)
+        }
+    }
 }
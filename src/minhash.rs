@@ -1,38 +1,102 @@
 //! Classic MinHash with K seeded permutations via splitmix64.
 //!
 //! Use when you want strict MinHash semantics. For speed on huge inputs,
-//! consider `kmv::KmvSketch`.
+//! consider `kmv::KmvSketch` or [`OnePermutationMinHash`].
 
-use crate::util::mix_with_seed;
+use crate::errors::SketchError;
+use crate::util::{Mixer, SplitMix64, SplitMix64Rng, hash_band, mix_with_seed, splitmix64};
+use alloc::vec;
+use alloc::vec::Vec;
 
-pub struct MinHash {
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Format tag written as the first byte of `MinHash::serialize` output.
+const SERIAL_VERSION: u8 = 1;
+
+/// Generic over the element [`Mixer`] (default [`SplitMix64`]); see
+/// [`MinHash::with_mixer`].
+#[derive(Clone)]
+pub struct MinHash<M = SplitMix64> {
+    seed0: u64,
+    // Fold of `seeds`, cheap to compare and stored alongside serialized sketches.
+    fingerprint: u64,
     seeds: Vec<u64>,
     mins: Vec<u64>,
+    updates: u64,
+    mixer: M,
 }
 
 impl MinHash {
     /// Create a MinHash with `num_hashes = bands * rows_per_band`.
     pub fn new(num_hashes: usize, seed0: u64) -> Self {
-        let mut seeds = Vec::with_capacity(num_hashes);
-        // Derive per-hash seeds from seed0 using splitmix64 chain.
-        let mut s = seed0;
-        for _ in 0..num_hashes {
-            s = crate::util::splitmix64(s);
-            seeds.push(s);
+        MinHash::with_mixer(num_hashes, seed0, SplitMix64)
+    }
+
+    /// Decode a sketch written by `serialize` into an updatable `MinHash`.
+    /// Fails if the re-derived seeds do not match the stored fingerprint.
+    /// The mixer is not serialized; this always yields a `SplitMix64` sketch.
+    pub fn deserialize(bytes: &[u8]) -> Result<MinHash, SketchError> {
+        let (&version, rest) = bytes
+            .split_first()
+            .ok_or(SketchError::DeserializeFailed("empty input"))?;
+        if version != SERIAL_VERSION {
+            return Err(SketchError::DeserializeFailed("unsupported format version"));
+        }
+        if rest.len() % 8 != 0 || rest.len() < 24 {
+            return Err(SketchError::DeserializeFailed("truncated input"));
+        }
+        let words: Vec<u64> = rest
+            .chunks_exact(8)
+            .map(|w| u64::from_le_bytes(w.try_into().expect("8-byte chunk")))
+            .collect();
+        let (seed0, num_hashes, fingerprint) = (words[0], words[1] as usize, words[2]);
+        if words.len() - 3 != num_hashes {
+            return Err(SketchError::DeserializeFailed(
+                "length does not match num_hashes",
+            ));
+        }
+        let mut sketch = MinHash::new(num_hashes, seed0);
+        if sketch.fingerprint != fingerprint {
+            return Err(SketchError::IncompatibleSeeds);
         }
+        sketch.mins.copy_from_slice(&words[3..]);
+        Ok(sketch)
+    }
+}
+
+impl<M: Mixer> MinHash<M> {
+    /// Like `new`, hashing elements with `mixer` instead of splitmix64.
+    /// Only sketches built with the same mixer (and seeds) are comparable.
+    pub fn with_mixer(num_hashes: usize, seed0: u64, mixer: M) -> Self {
+        // Derive per-hash seeds from seed0 using splitmix64 chain.
+        let mut rng = SplitMix64Rng::new(seed0);
+        let seeds: Vec<u64> = (0..num_hashes).map(|_| rng.next_u64()).collect();
         Self {
+            seed0,
+            fingerprint: hash_band(&seeds, num_hashes as u64),
             mins: vec![u64::MAX; num_hashes],
             seeds,
+            updates: 0,
+            mixer,
         }
     }
 
+    /// Fingerprint of the permutation seeds. Sketches can only be merged or
+    /// compared position-wise when their fingerprints match.
+    #[inline]
+    pub fn seed_fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
     /// Update with a pre-hashed k-mer value (u64).
     #[inline]
     pub fn update(&mut self, x: u64) {
+        self.updates += 1;
         let n = self.seeds.len();
         // Tight loop: branchless update of minima across all seeds.
         for i in 0..n {
-            let h = mix_with_seed(x, self.seeds[i]);
+            let h = self.mixer.mix(x, self.seeds[i]);
             // Min update
             if h < self.mins[i] {
                 self.mins[i] = h;
@@ -40,8 +104,414 @@ impl MinHash {
         }
     }
 
+    /// Update with a batch of pre-hashed k-mers; bit-identical to calling
+    /// `update` for each element.
+    ///
+    /// Loops seed-major so each running minimum stays in a register while the
+    /// inner loop over `xs` is free to vectorize.
+    pub fn update_all(&mut self, xs: &[u64]) {
+        self.updates += xs.len() as u64;
+        for (min, &seed) in self.mins.iter_mut().zip(&self.seeds) {
+            let mut m = *min;
+            for &x in xs {
+                m = m.min(self.mixer.mix(x, seed));
+            }
+            *min = m;
+        }
+    }
+
+    /// Parallel `update_all`, bit-identical to it: the seed range is split
+    /// across rayon threads, each scanning the whole batch for its own
+    /// contiguous, disjoint slice of `mins` (so threads never share a cache
+    /// line except at range boundaries).
+    ///
+    /// Only pays off for large `num_hashes` (hundreds) and large batches;
+    /// for small sketches or a few elements rayon's overhead dominates and
+    /// `update_all` is faster.
+    #[cfg(feature = "parallel")]
+    pub fn update_all_parallel(&mut self, xs: &[u64])
+    where
+        M: Sync,
+    {
+        self.updates += xs.len() as u64;
+        let mixer = &self.mixer;
+        self.mins
+            .par_iter_mut()
+            .zip(self.seeds.par_iter())
+            .with_min_len(32)
+            .for_each(|(min, &seed)| {
+                let mut m = *min;
+                for &x in xs {
+                    m = m.min(mixer.mix(x, seed));
+                }
+                *min = m;
+            });
+    }
+
+    /// Merge `other` into `self` by taking the element-wise minimum.
+    ///
+    /// Both instances must share the same seeds (same `num_hashes` and `seed0`).
+    /// Merging is associative and commutative, so partial sketches can be
+    /// combined in any order, e.g. with `reduce`.
+    pub fn merge(&mut self, other: &MinHash<M>) -> Result<(), SketchError> {
+        if self.mins.len() != other.mins.len() || self.fingerprint != other.fingerprint {
+            return Err(SketchError::IncompatibleSeeds);
+        }
+        self.updates += other.updates;
+        for (m, &o) in self.mins.iter_mut().zip(&other.mins) {
+            if o < *m {
+                *m = o;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the sketch as bytes: a one-byte format version, then `seed0`,
+    /// `num_hashes`, the seed fingerprint and the current minima, all as
+    /// little-endian u64. Seeds are re-derived from `seed0` on load.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 + 8 * (3 + self.mins.len()));
+        out.push(SERIAL_VERSION);
+        for w in [self.seed0, self.mins.len() as u64, self.fingerprint] {
+            out.extend_from_slice(&w.to_le_bytes());
+        }
+        for m in &self.mins {
+            out.extend_from_slice(&m.to_le_bytes());
+        }
+        out
+    }
+
+    /// Forget every update, keeping `seeds` and the `mins` allocation, so one
+    /// sketch can be reused across many sequences.
+    pub fn reset(&mut self) {
+        self.mins.fill(u64::MAX);
+        self.updates = 0;
+    }
+
+    /// Number of values fed through `update`/`update_all` since construction
+    /// or `reset`, duplicates included; this is not a distinct count. Merging
+    /// adds the other sketch's count; deserialized sketches start at zero.
+    #[inline]
+    pub fn num_updates(&self) -> u64 {
+        self.updates
+    }
+
+    /// Snapshot of the current signature without consuming the sketch, e.g.
+    /// to watch the estimate converge while updates keep arriving.
+    pub fn signature(&self) -> Vec<u64> {
+        self.mins.clone()
+    }
+
     /// Final signature (length = num_hashes).
     pub fn finish(self) -> Vec<u64> {
         self.mins
     }
 }
+
+/// Half-size MinHash: each slot keeps the minimum of the **high 32 bits** of
+/// the same permuted hashes as [`MinHash`], so `MinHash32::new(n, seed)`
+/// yields exactly `MinHash::new(n, seed)`'s signature shifted right by 32.
+///
+/// Signatures take 4 bytes per slot, at a precision cost that grows with set
+/// size. A slot's minimum over `m` distinct hashes sits near `2^32 / m` in the
+/// 32-bit space, so the minima of two different sets of about `m` elements
+/// agree by chance with probability roughly `m / 2^33` per slot: about 1e-5
+/// at `m = 1e5`, 6e-4 at `m = 5e6` and 10% at `m = 1e9`. Jaccard estimates
+/// are biased upwards by about that much, and every row gains false
+/// agreements at the same rate, raising band collisions between dissimilar
+/// sets. Use the 64-bit [`MinHash`] for sets of millions of elements or more,
+/// or whenever low similarities must be estimated precisely.
+/// Compare with `sketch::jaccard_from_signatures_u32`; index with
+/// `LshIndex::insert_u32` / `query_candidates_u32`.
+#[derive(Clone)]
+pub struct MinHash32 {
+    seeds: Vec<u64>,
+    mins: Vec<u32>,
+}
+
+impl MinHash32 {
+    pub fn new(num_hashes: usize, seed0: u64) -> Self {
+        let mut rng = SplitMix64Rng::new(seed0);
+        let seeds: Vec<u64> = (0..num_hashes).map(|_| rng.next_u64()).collect();
+        Self {
+            seeds,
+            mins: vec![u32::MAX; num_hashes],
+        }
+    }
+
+    /// Update with a pre-hashed k-mer value (u64).
+    #[inline]
+    pub fn update(&mut self, x: u64) {
+        for (min, &seed) in self.mins.iter_mut().zip(&self.seeds) {
+            *min = (*min).min((mix_with_seed(x, seed) >> 32) as u32);
+        }
+    }
+
+    /// Batch `update`; bit-identical to calling it per element.
+    pub fn update_all(&mut self, xs: &[u64]) {
+        for (min, &seed) in self.mins.iter_mut().zip(&self.seeds) {
+            let mut m = *min;
+            for &x in xs {
+                m = m.min((mix_with_seed(x, seed) >> 32) as u32);
+            }
+            *min = m;
+        }
+    }
+
+    /// Final signature (length = num_hashes).
+    pub fn finish(self) -> Vec<u32> {
+        self.mins
+    }
+}
+
+/// Pack the low `b` bits (1..=64) of every signature slot into a bitvector.
+///
+/// Layout: word 0 holds the number of packed slots; slot `i` then occupies
+/// bits `i*b .. (i+1)*b` of the following words (little-endian bit order, a
+/// slot may straddle two words). Compare compressed signatures with
+/// `sketch::jaccard_b_bit`.
+///
+/// b-bit signatures trade accuracy for memory: two unrelated slots agree with
+/// probability `2^-b`, which the estimator corrects for but which inflates its
+/// variance by roughly `1 / (1 - 2^-b)^2` compared to full MinHash at the same
+/// length. With b=1 you need ~4x more slots for the same error, yet still use
+/// far less memory than 64-bit slots.
+pub fn b_bit_compress(signature: &[u64], b: u32) -> Vec<u64> {
+    assert!((1..=64).contains(&b), "b must be in 1..=64 (got {b})");
+    let total_bits = signature.len() * b as usize;
+    let mut out = vec![0u64; 1 + total_bits.div_ceil(64)];
+    out[0] = signature.len() as u64;
+    let mask = if b == 64 { u64::MAX } else { (1u64 << b) - 1 };
+    for (i, &v) in signature.iter().enumerate() {
+        let bits = v & mask;
+        let pos = i * b as usize;
+        let (word, offset) = (1 + pos / 64, pos % 64);
+        out[word] |= bits << offset;
+        if offset + b as usize > 64 {
+            out[word + 1] |= bits >> (64 - offset);
+        }
+    }
+    out
+}
+
+/// One-permutation MinHash (OPH) with optimal densification.
+///
+/// Each element is hashed once and routed to one of `num_hashes` slots by the
+/// high bits of its hash; every slot keeps its minimum. This makes `update`
+/// O(1) instead of O(num_hashes). Empty slots are filled at `finish` by
+/// borrowing from a pseudo-randomly probed non-empty slot, so the output is a
+/// fixed-length signature usable with `LshIndex` and `jaccard_from_signatures`.
+pub struct OnePermutationMinHash {
+    seed: u64,
+    mins: Vec<u64>,
+    filled: Vec<bool>,
+}
+
+impl OnePermutationMinHash {
+    pub fn new(num_hashes: usize, seed: u64) -> Self {
+        Self {
+            seed,
+            mins: vec![u64::MAX; num_hashes],
+            filled: vec![false; num_hashes],
+        }
+    }
+
+    /// Update with a pre-hashed k-mer value (u64).
+    #[inline]
+    pub fn update(&mut self, x: u64) {
+        let n = self.mins.len();
+        if n == 0 {
+            return;
+        }
+        let h = mix_with_seed(x, self.seed);
+        // Map the hash onto 0..n by its high bits (multiply-shift).
+        let slot = ((h as u128 * n as u128) >> 64) as usize;
+        self.filled[slot] = true;
+        if h < self.mins[slot] {
+            self.mins[slot] = h;
+        }
+    }
+
+    /// Final densified signature (length = num_hashes). If no element was
+    /// observed, all slots stay `u64::MAX`.
+    pub fn finish(mut self) -> Vec<u64> {
+        let n = self.mins.len();
+        if !self.filled.iter().any(|&f| f) {
+            return self.mins;
+        }
+        for i in 0..n {
+            if self.filled[i] {
+                continue;
+            }
+            // Probe slots with a per-(slot, attempt) hash until a non-empty one
+            // is found; the probe sequence depends only on `i` and the seed.
+            let mut attempt = 0u64;
+            loop {
+                attempt += 1;
+                let r = mix_with_seed((i as u64) << 32 | attempt, self.seed);
+                let j = ((r as u128 * n as u128) >> 64) as usize;
+                if self.filled[j] {
+                    self.mins[i] = self.mins[j];
+                    break;
+                }
+            }
+        }
+        self.mins
+    }
+}
+
+/// Weighted MinHash via improved consistent weighted sampling (ICWS, Ioffe 2010).
+///
+/// Inputs are `(element, weight)` pairs, e.g. k-mer hash and coverage. Each
+/// signature slot samples one `(element, t)` pair, consistently across sets,
+/// such that two signatures agree at a slot with probability equal to the
+/// weighted Jaccard `Σ min(w_a, w_b) / Σ max(w_a, w_b)`. `finish` returns a
+/// `Vec<u64>` compatible with `LshIndex` banding and `jaccard_from_signatures`.
+///
+/// Each update costs five pseudo-random draws plus a few `ln`/`exp` per slot,
+/// several times the per-slot cost of [`MinHash::update`].
+#[cfg(feature = "std")]
+pub struct WeightedMinHash {
+    seeds: Vec<u64>,
+    best_a: Vec<f64>,
+    best: Vec<u64>,
+}
+
+#[cfg(feature = "std")]
+impl WeightedMinHash {
+    pub fn new(num_hashes: usize, seed0: u64) -> Self {
+        let mut rng = SplitMix64Rng::new(seed0);
+        let seeds: Vec<u64> = (0..num_hashes).map(|_| rng.next_u64()).collect();
+        Self {
+            seeds,
+            best_a: vec![f64::INFINITY; num_hashes],
+            best: vec![u64::MAX; num_hashes],
+        }
+    }
+
+    /// Update with a pre-hashed element and its weight. Weights must be
+    /// non-negative and finite; zero-weight elements are ignored.
+    pub fn update(&mut self, element: u64, weight: f64) {
+        assert!(
+            weight >= 0.0 && weight.is_finite(),
+            "weights must be non-negative and finite (got {weight})"
+        );
+        if weight == 0.0 {
+            return;
+        }
+        let ln_w = weight.ln();
+        for i in 0..self.seeds.len() {
+            // Per-(slot, element) random stream: depends only on seed and element.
+            let mut state = mix_with_seed(element, self.seeds[i]);
+            let mut uniform = || {
+                state = splitmix64(state);
+                // (0, 1), both ends excluded so `ln` stays finite.
+                ((state >> 11) as f64 + 0.5) * (1.0 / (1u64 << 53) as f64)
+            };
+            let r = -(uniform().ln() + uniform().ln()); // Gamma(2, 1)
+            let c = -(uniform().ln() + uniform().ln()); // Gamma(2, 1)
+            let beta = uniform();
+            let t = (ln_w / r + beta).floor();
+            let ln_y = r * (t - beta);
+            let a = c / (ln_y + r).exp();
+            if a < self.best_a[i] {
+                self.best_a[i] = a;
+                self.best[i] = mix_with_seed(element ^ splitmix64(t as i64 as u64), self.seeds[i]);
+            }
+        }
+    }
+
+    /// Final signature (length = num_hashes). Slots stay `u64::MAX` if no
+    /// positive-weight element was observed.
+    pub fn finish(self) -> Vec<u64> {
+        self.best
+    }
+}
+
+/// SuperMinHash (Ertl 2017): a MinHash variant whose slot values come from a
+/// shared random permutation per element, giving lower estimation variance
+/// than classic [`MinHash`] at the same signature length, most noticeably
+/// when sets are not much larger than `num_hashes`.
+///
+/// Per-element work shrinks as the signature fills thanks to the histogram
+/// of slot values (early stopping), so updates approach O(1) on large sets.
+/// `finish` returns each slot's value (a float in `[0, num_hashes)`) as its
+/// IEEE-754 bit pattern, which is order-preserving for non-negative floats
+/// and compatible with banding and `jaccard_from_signatures`.
+pub struct SuperMinHash {
+    seed: u64,
+    h: Vec<f64>,
+    p: Vec<usize>,
+    q: Vec<u64>,
+    hist: Vec<usize>,
+    a: usize,
+    count: u64,
+}
+
+impl SuperMinHash {
+    pub fn new(num_hashes: usize, seed: u64) -> Self {
+        let mut hist = vec![0; num_hashes];
+        if let Some(last) = hist.last_mut() {
+            *last = num_hashes;
+        }
+        Self {
+            seed,
+            h: vec![f64::INFINITY; num_hashes],
+            p: vec![0; num_hashes],
+            q: vec![u64::MAX; num_hashes],
+            hist,
+            a: num_hashes.saturating_sub(1),
+            count: 0,
+        }
+    }
+
+    /// Update with a pre-hashed k-mer value (u64).
+    pub fn update(&mut self, x: u64) {
+        let m = self.h.len();
+        if m == 0 {
+            return;
+        }
+        let i = self.count;
+        self.count += 1;
+        let mut state = mix_with_seed(x, self.seed);
+        let mut next = || {
+            state = splitmix64(state);
+            state
+        };
+        let mut j = 0;
+        while j <= self.a {
+            let r = (next() >> 11) as f64 * (1.0 / (1u64 << 53) as f64);
+            let k = j + ((next() as u128 * (m - j) as u128) >> 64) as usize;
+            if self.q[j] != i {
+                self.q[j] = i;
+                self.p[j] = j;
+            }
+            if self.q[k] != i {
+                self.q[k] = i;
+                self.p[k] = k;
+            }
+            self.p.swap(j, k);
+            let slot = self.p[j];
+            if r + (j as f64) < self.h[slot] {
+                let prev = (self.h[slot].min((m - 1) as f64)) as usize;
+                self.h[slot] = r + j as f64;
+                if j < prev {
+                    self.hist[prev] -= 1;
+                    self.hist[j] += 1;
+                    while self.a > 0 && self.hist[self.a] == 0 {
+                        self.a -= 1;
+                    }
+                }
+            }
+            j += 1;
+        }
+    }
+
+    /// Final signature (length = num_hashes). Empty slots are `u64::MAX`.
+    pub fn finish(self) -> Vec<u64> {
+        self.h
+            .into_iter()
+            .map(|v| if v.is_finite() { v.to_bits() } else { u64::MAX })
+            .collect()
+    }
+}
@@ -1,7 +1,36 @@
 //! Sketch utilities common to MinHash and KMV.
+//!
+//! Pick the estimator that matches the sketch type:
+//! - MinHash signatures: [`jaccard_from_signatures`] (per-position equality;
+//!   [`try_jaccard_from_signatures`] additionally rejects length mismatches),
+//! - KMV signatures: [`jaccard_kmv`] (bottom-k of the union). Positional
+//!   comparison is meaningless for KMV, since one extra small value shifts
+//!   every later position.
+//!
+//! Containment (`|Q ∩ T| / |Q|`) is asymmetric; use
+//! [`containment_from_signatures`] or [`containment_kmv`] with the query first.
+//!
+//! [`FracMinHash`] signatures are exact sorted subsets of the hash space, so
+//! [`jaccard_frac`] and [`containment_frac`] compare them by set merge;
+//! [`jaccard_sorted_sets`] does the same for any two sorted value sets.
+//!
+//! [`SketchExt`] sketches any `Iterator<Item = u64>` directly.
+
+use crate::errors::SketchError;
+use crate::kmv::KmvSketch;
+use crate::minhash::MinHash;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
 /// Compute a MinHash-style Jaccard estimate from two signatures of equal length.
 /// This function treats signatures as MinHash-like: equality per position.
+///
+/// Lenient: only the first `min(a.len(), b.len())` positions are compared. For
+/// fixed-length MinHash signatures prefer [`try_jaccard_from_signatures`],
+/// which catches signatures from mismatched sketch configurations.
 pub fn jaccard_from_signatures(a: &[u64], b: &[u64]) -> f64 {
     let n = a.len().min(b.len());
     if n == 0 {
@@ -15,3 +44,515 @@ pub fn jaccard_from_signatures(a: &[u64], b: &[u64]) -> f64 {
     }
     eq as f64 / n as f64
 }
+
+/// [`jaccard_from_signatures`] for 32-bit signatures such as
+/// `minhash::MinHash32` output; same lenient prefix semantics.
+pub fn jaccard_from_signatures_u32(a: &[u32], b: &[u32]) -> f64 {
+    let n = a.len().min(b.len());
+    if n == 0 {
+        return 0.0;
+    }
+    let eq = a.iter().zip(b).filter(|(x, y)| x == y).count();
+    eq as f64 / n as f64
+}
+
+/// [`jaccard_from_signatures`] with a weight per position: the weighted
+/// fraction `sum(w[i] for matching i) / sum(w)`, e.g. to downweight slots
+/// found to be noisy. Equal weights give exactly the unweighted estimate.
+///
+/// # Panics
+/// Unless `a`, `b` and `weights` all have the same length and the weights
+/// sum to a positive value.
+pub fn jaccard_weighted_positions(a: &[u64], b: &[u64], weights: &[f64]) -> f64 {
+    assert!(
+        a.len() == b.len() && a.len() == weights.len(),
+        "need one weight per signature position"
+    );
+    let total: f64 = weights.iter().sum();
+    assert!(total > 0.0, "weights must sum to a positive value");
+    let matched: f64 = a
+        .iter()
+        .zip(b)
+        .zip(weights)
+        .filter(|((x, y), _)| x == y)
+        .map(|(_, &w)| w)
+        .sum();
+    matched / total
+}
+
+/// Strict variant of [`jaccard_from_signatures`]: fails with
+/// `SketchError::LengthMismatch` unless both signatures have the same length.
+pub fn try_jaccard_from_signatures(a: &[u64], b: &[u64]) -> Result<f64, SketchError> {
+    if a.len() != b.len() {
+        return Err(SketchError::LengthMismatch {
+            left: a.len(),
+            right: b.len(),
+        });
+    }
+    Ok(jaccard_from_signatures(a, b))
+}
+
+/// Number of positions where two MinHash signatures disagree.
+///
+/// Like `jaccard_from_signatures`, only the first `min(a.len(), b.len())`
+/// positions are compared.
+pub fn minhash_distance(a: &[u64], b: &[u64]) -> usize {
+    a.iter().zip(b).filter(|(x, y)| x != y).count()
+}
+
+/// Fraction of agreeing positions, `1 - distance / n`; identical to
+/// `jaccard_from_signatures` (including 0 for empty signatures).
+pub fn minhash_similarity(a: &[u64], b: &[u64]) -> f64 {
+    let n = a.len().min(b.len());
+    if n == 0 {
+        return 0.0;
+    }
+    1.0 - minhash_distance(a, b) as f64 / n as f64
+}
+
+/// Positional Jaccard estimate with a two-sided confidence interval:
+/// `(estimate, lower, upper)`, bounds clamped to `[0, 1]`.
+///
+/// Each of the `n` positions matches with probability J, so the estimate is a
+/// binomial proportion with standard error `sqrt(J (1 - J) / n)`. The interval
+/// is the normal (Wald) approximation `estimate ± z * se`; it degrades for
+/// small signatures and for estimates near 0 or 1, where it collapses to a
+/// point. Empty signatures give `(0, 0, 1)`.
+#[cfg(feature = "std")]
+pub fn jaccard_with_ci(a: &[u64], b: &[u64], confidence: f64) -> (f64, f64, f64) {
+    assert!(
+        confidence > 0.0 && confidence < 1.0,
+        "confidence must lie strictly between 0 and 1 (got {confidence})"
+    );
+    let n = a.len().min(b.len());
+    if n == 0 {
+        return (0.0, 0.0, 1.0);
+    }
+    let j = jaccard_from_signatures(a, b);
+    let z = normal_quantile(0.5 + confidence / 2.0);
+    let half = z * (j * (1.0 - j) / n as f64).sqrt();
+    (j, (j - half).max(0.0), (j + half).min(1.0))
+}
+
+/// Standard normal quantile for `p` in (0, 1), via Abramowitz & Stegun
+/// 26.2.23 (absolute error < 4.5e-4).
+#[cfg(feature = "std")]
+fn normal_quantile(p: f64) -> f64 {
+    let tail = if p < 0.5 { p } else { 1.0 - p };
+    let t = (-2.0 * tail.ln()).sqrt();
+    let x = t
+        - (2.515517 + 0.802853 * t + 0.010328 * t * t)
+            / (1.0 + 1.432788 * t + 0.189269 * t * t + 0.001308 * t * t * t);
+    if p < 0.5 { -x } else { x }
+}
+
+/// Bottom-k Jaccard estimate for two KMV signatures (sorted ascending, as
+/// produced by `KmvSketch::finish`).
+///
+/// Takes the k smallest distinct values of the union and returns the fraction
+/// that occur in both signatures.
+pub fn jaccard_kmv(a: &[u64], b: &[u64], k: usize) -> f64 {
+    let u = bottom_k_union(a, b, k);
+    if u.taken == 0 {
+        return 0.0;
+    }
+    u.shared as f64 / u.taken as f64
+}
+
+/// [`jaccard_kmv`] with the effective k taken as the shorter signature's
+/// length, for KMV signatures of differing lengths (e.g. under-filled
+/// sketches of short sequences) when the sketch `k` is not at hand.
+///
+/// Any signature holds the true bottom-`len` of its set, so the bottom-k of
+/// the union for `k = min(a.len(), b.len())` is still exact and the estimate
+/// stays valid. What suffers is precision: it rests on only `k` samples, so
+/// an under-filled sketch caps resolution at `1 / k`, and high similarity is
+/// impossible when one set is much smaller than the other. When both
+/// signatures are under-filled they hold their whole sets, and
+/// `jaccard_kmv(a, b, a.len() + b.len())` gives the exact Jaccard instead.
+pub fn jaccard_kmv_robust(a: &[u64], b: &[u64]) -> f64 {
+    jaccard_kmv(a, b, a.len().min(b.len()))
+}
+
+/// Estimated number of distinct elements in the union of the two sets behind
+/// KMV signatures `a` and `b` (sorted ascending), from the bottom-k of their
+/// merge as in [`jaccard_kmv`].
+///
+/// With the k-th smallest union value `h_k` normalized to `[0, 1]`, the
+/// estimate is `(k - 1) / h_k`; if the union holds fewer than `k` distinct
+/// values it is counted exactly. Relative error is about `1 / sqrt(k - 2)`.
+pub fn kmv_union_cardinality(a: &[u64], b: &[u64], k: usize) -> f64 {
+    union_cardinality(&bottom_k_union(a, b, k), k)
+}
+
+/// Estimated size of the intersection of the sets behind two KMV signatures:
+/// [`jaccard_kmv`] times [`kmv_union_cardinality`].
+///
+/// Accuracy depends on k and on the relative set sizes: only the shared
+/// fraction of the union's bottom-k is observed, so a small intersection of
+/// two large or very unequal sets rests on few samples and has a high
+/// relative error.
+pub fn kmv_intersection_cardinality(a: &[u64], b: &[u64], k: usize) -> f64 {
+    let u = bottom_k_union(a, b, k);
+    if u.taken == 0 {
+        return 0.0;
+    }
+    u.shared as f64 / u.taken as f64 * union_cardinality(&u, k)
+}
+
+/// Fraction of the u64 hash space below the largest value of a sorted KMV
+/// signature, `max / u64::MAX` (0 for an empty signature).
+///
+/// A full bottom-k over `n` distinct elements covers about `k / n` of the
+/// space, so coverage near 1 flags a sketch of few distinct elements
+/// relative to `k` (or a padded one), whose Jaccard estimates rest on few
+/// samples and deserve less trust.
+pub fn kmv_coverage(signature: &[u64]) -> f64 {
+    match signature.last() {
+        Some(&max) => max as f64 / u64::MAX as f64,
+        None => 0.0,
+    }
+}
+
+fn union_cardinality(u: &UnionCounts, k: usize) -> f64 {
+    if u.taken < k || u.taken < 2 {
+        return u.taken as f64;
+    }
+    (u.taken - 1) as f64 / (u.last as f64 / u64::MAX as f64)
+}
+
+/// Containment of `query` in `target` from two MinHash signatures: the
+/// estimated fraction of the query set that is also in the target set.
+///
+/// Argument order matters: `containment(q, t)` is `|Q ∩ T| / |Q|`. MinHash
+/// signatures do not carry set sizes, so both cardinalities are estimated from
+/// the mean normalized minimum and combined with the positional Jaccard via
+/// `|Q ∩ T| = J (|Q| + |T|) / (1 + J)`. Result is clamped to `[0, 1]`.
+pub fn containment_from_signatures(query: &[u64], target: &[u64]) -> f64 {
+    let j = jaccard_from_signatures(query, target);
+    let (nq, nt) = (minhash_cardinality(query), minhash_cardinality(target));
+    if j == 0.0 || nq <= 0.0 {
+        return 0.0;
+    }
+    (j * (nq + nt) / ((1.0 + j) * nq)).clamp(0.0, 1.0)
+}
+
+/// Containment of `query` in `target` from two KMV signatures (sorted
+/// ascending): among the bottom-k of the union that belong to the query, the
+/// fraction also present in the target. Argument order matters, as above.
+pub fn containment_kmv(query: &[u64], target: &[u64], k: usize) -> f64 {
+    let u = bottom_k_union(query, target, k);
+    if u.in_a == 0 {
+        return 0.0;
+    }
+    u.shared as f64 / u.in_a as f64
+}
+
+/// Sørensen–Dice coefficient `2 |A ∩ B| / (|A| + |B|)` from two MinHash
+/// signatures.
+///
+/// The fraction of equal positions estimates the Jaccard J; Dice follows
+/// exactly as `2J / (1 + J)`, with no set sizes needed. Inherits the
+/// estimator's assumptions: same seeds, equal-length signatures.
+pub fn dice_from_signatures(a: &[u64], b: &[u64]) -> f64 {
+    let j = jaccard_from_signatures(a, b);
+    2.0 * j / (1.0 + j)
+}
+
+/// Overlap (Szymkiewicz–Simpson) coefficient `|A ∩ B| / min(|A|, |B|)` from
+/// two MinHash signatures.
+///
+/// Unlike Dice this depends on the set sizes, which MinHash does not record:
+/// as in [`containment_from_signatures`], both cardinalities are estimated
+/// from the mean normalized minimum and combined with the positional Jaccard
+/// via `|A ∩ B| = J (|A| + |B|) / (1 + J)`. Cardinality estimates are noisy
+/// for short signatures, so this is coarser than Jaccard or Dice. Result is
+/// clamped to `[0, 1]`.
+pub fn overlap_coefficient(a: &[u64], b: &[u64]) -> f64 {
+    let j = jaccard_from_signatures(a, b);
+    let (na, nb) = (minhash_cardinality(a), minhash_cardinality(b));
+    let smaller = na.min(nb);
+    if j == 0.0 || smaller <= 0.0 {
+        return 0.0;
+    }
+    (j * (na + nb) / ((1.0 + j) * smaller)).clamp(0.0, 1.0)
+}
+
+/// Counts over the k smallest distinct values of the union of two sorted sets.
+pub(crate) struct UnionCounts {
+    pub taken: usize,
+    pub in_a: usize,
+    pub shared: usize,
+    // Largest value taken (the k-th smallest of the union once `taken == k`).
+    pub last: u64,
+}
+
+pub(crate) fn bottom_k_union(a: &[u64], b: &[u64], k: usize) -> UnionCounts {
+    let (mut i, mut j) = (0usize, 0usize);
+    let mut u = UnionCounts {
+        taken: 0,
+        in_a: 0,
+        shared: 0,
+        last: 0,
+    };
+    while u.taken < k && (i < a.len() || j < b.len()) {
+        match (a.get(i), b.get(j)) {
+            (Some(&x), Some(&y)) if x == y => {
+                u.shared += 1;
+                u.in_a += 1;
+                u.last = x;
+                i += 1;
+                j += 1;
+            }
+            (Some(&x), Some(&y)) if x > y => {
+                u.last = y;
+                j += 1;
+            }
+            (Some(&x), _) => {
+                u.in_a += 1;
+                u.last = x;
+                i += 1;
+            }
+            (_, Some(&y)) => {
+                u.last = y;
+                j += 1;
+            }
+            (None, None) => unreachable!(),
+        }
+        u.taken += 1;
+    }
+    u
+}
+
+/// Cardinality estimate from a MinHash signature: each slot's minimum,
+/// normalized to `[0, 1)`, has expectation `1 / (n + 1)`.
+fn minhash_cardinality(sig: &[u64]) -> f64 {
+    let sum: f64 = sig.iter().map(|&m| m as f64 / u64::MAX as f64).sum();
+    if sum == 0.0 {
+        return f64::INFINITY;
+    }
+    (sig.len() as f64 / sum - 1.0).max(0.0)
+}
+
+/// Jaccard estimate from two signatures compressed with
+/// `minhash::b_bit_compress` using the same `b_bits`.
+///
+/// Applies the standard b-bit correction under the sparse-set approximation:
+/// unrelated slots collide with probability `2^-b`, so
+/// `J = (P_match - 2^-b) / (1 - 2^-b)`, clamped to `[0, 1]`.
+pub fn jaccard_b_bit(a: &[u64], b: &[u64], b_bits: u32) -> f64 {
+    assert!(
+        (1..=64).contains(&b_bits),
+        "b_bits must be in 1..=64 (got {b_bits})"
+    );
+    let (Some(&na), Some(&nb)) = (a.first(), b.first()) else {
+        return 0.0;
+    };
+    let n = na.min(nb) as usize;
+    if n == 0 {
+        return 0.0;
+    }
+    let width = b_bits as usize;
+    let mask = if b_bits == 64 {
+        u64::MAX
+    } else {
+        (1u64 << b_bits) - 1
+    };
+    let slot = |words: &[u64], i: usize| {
+        let pos = i * width;
+        let (word, offset) = (1 + pos / 64, pos % 64);
+        let mut v = words[word] >> offset;
+        if offset + width > 64 {
+            v |= words[word + 1] << (64 - offset);
+        }
+        v & mask
+    };
+    let eq = (0..n).filter(|&i| slot(a, i) == slot(b, i)).count();
+    let p = eq as f64 / n as f64;
+    let c = 1.0 / (1u128 << b_bits) as f64; // exact 2^-b
+    ((p - c) / (1.0 - c)).clamp(0.0, 1.0)
+}
+
+/// FracMinHash (scaled MinHash): keeps every hash below `scale * 2^64`, so
+/// the signature is a uniform ~`scale` fraction of the set and grows with it.
+///
+/// Unlike bottom-k sketches, two FracMinHash signatures sample the same
+/// region of hash space regardless of set sizes, which keeps containment
+/// unbiased when comparing sets of very different sizes (e.g. a genome
+/// against a metagenome). Signatures are only comparable at equal `scale`.
+#[derive(Clone)]
+pub struct FracMinHash {
+    scale: f64,
+    threshold: u64,
+    kept: BTreeSet<u64>,
+}
+
+impl FracMinHash {
+    /// # Panics
+    /// Unless `0 < scale <= 1`.
+    pub fn new(scale: f64) -> Self {
+        assert!(
+            scale > 0.0 && scale <= 1.0,
+            "scale must lie in (0, 1] (got {scale})"
+        );
+        Self {
+            scale,
+            // Saturates at u64::MAX for scale == 1.
+            threshold: (scale * u64::MAX as f64) as u64,
+            kept: BTreeSet::new(),
+        }
+    }
+
+    /// Update with a pre-hashed k-mer value (u64). Repeated values are ignored.
+    #[inline]
+    pub fn update(&mut self, h: u64) {
+        if h < self.threshold {
+            self.kept.insert(h);
+        }
+    }
+
+    #[inline]
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+
+    /// Number of distinct hashes kept so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.kept.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.kept.is_empty()
+    }
+
+    /// Kept hashes, sorted ascending and distinct.
+    pub fn finish(self) -> Vec<u64> {
+        self.kept.into_iter().collect()
+    }
+}
+
+/// Jaccard `|A ∩ B| / |A ∪ B|` of two FracMinHash signatures built with the
+/// same `scale`. Empty signatures give 0.
+pub fn jaccard_frac(a: &[u64], b: &[u64]) -> f64 {
+    // FracMinHash signatures are ascending sets, so this is their exact
+    // set Jaccard.
+    jaccard_sorted_sets(a, b)
+}
+
+/// Containment of `query` in `target`, `|Q ∩ T| / |Q|`, from FracMinHash
+/// signatures built with the same `scale`. Argument order matters.
+pub fn containment_frac(query: &[u64], target: &[u64]) -> f64 {
+    if query.is_empty() {
+        return 0.0;
+    }
+    sorted_intersection_len(query, target) as f64 / query.len() as f64
+}
+
+/// Exact Jaccard `|A ∩ B| / |A ∪ B|` of two sorted value sets, by a linear
+/// two-pointer merge. Both inputs must be strictly ascending, as
+/// `KmvSketch::finish` returns (checked in debug builds). Empty inputs give 0.
+///
+/// This is the Jaccard of the given sets themselves. For two KMV signatures
+/// it compares the kept minima as sets, unlike the positional
+/// [`jaccard_from_signatures`]; to estimate the Jaccard of the underlying
+/// k-mer sets from bottom-k sketches, use [`jaccard_kmv`].
+pub fn jaccard_sorted_sets(a: &[u64], b: &[u64]) -> f64 {
+    debug_assert!(
+        a.windows(2).all(|w| w[0] < w[1]),
+        "jaccard_sorted_sets: `a` is not strictly ascending"
+    );
+    debug_assert!(
+        b.windows(2).all(|w| w[0] < w[1]),
+        "jaccard_sorted_sets: `b` is not strictly ascending"
+    );
+    let shared = sorted_intersection_len(a, b);
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        return 0.0;
+    }
+    shared as f64 / union as f64
+}
+
+/// Number of values shared by two ascending, duplicate-free slices.
+fn sorted_intersection_len(a: &[u64], b: &[u64]) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            core::cmp::Ordering::Less => i += 1,
+            core::cmp::Ordering::Greater => j += 1,
+            core::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    shared
+}
+
+/// Build one MinHash signature (`MinHash::new(num_hashes, seed)`) per input
+/// `(id, hashed k-mers)`, keeping the input order. With the `parallel`
+/// feature inputs are sketched on rayon's global pool; the output is the
+/// same either way.
+pub fn build_minhash_signatures(
+    inputs: &[(u32, Vec<u64>)],
+    num_hashes: usize,
+    seed: u64,
+) -> Vec<(u32, Vec<u64>)> {
+    let build = |(id, hashes): &(u32, Vec<u64>)| {
+        let mut sketch = MinHash::new(num_hashes, seed);
+        sketch.update_all(hashes);
+        (*id, sketch.finish())
+    };
+    #[cfg(feature = "parallel")]
+    {
+        inputs.par_iter().map(build).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        inputs.iter().map(build).collect()
+    }
+}
+
+/// KMV counterpart of [`build_minhash_signatures`]: one bottom-`k` signature
+/// per input, in input order (shorter than `k` for inputs with fewer than `k`
+/// distinct hashes).
+pub fn build_kmv_signatures(inputs: &[(u32, Vec<u64>)], k: usize) -> Vec<(u32, Vec<u64>)> {
+    let build = |(id, hashes): &(u32, Vec<u64>)| {
+        let mut sketch = KmvSketch::new(k);
+        hashes.iter().for_each(|&h| sketch.update(h));
+        (*id, sketch.finish())
+    };
+    #[cfg(feature = "parallel")]
+    {
+        inputs.par_iter().map(build).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        inputs.iter().map(build).collect()
+    }
+}
+
+/// Sketch an iterator of pre-hashed elements in one call, e.g.
+/// `hashes.into_iter().kmv_sketch(128)`. Same signatures as building a
+/// [`MinHash`] or [`KmvSketch`], updating with every element and finishing.
+pub trait SketchExt: Iterator<Item = u64> + Sized {
+    /// `MinHash::new(num_hashes, seed)` signature of the elements.
+    fn minhash_sketch(self, num_hashes: usize, seed: u64) -> Vec<u64> {
+        let mut sketch = MinHash::new(num_hashes, seed);
+        self.for_each(|x| sketch.update(x));
+        sketch.finish()
+    }
+
+    /// `KmvSketch::new(k)` bottom-`k` signature of the elements (shorter than
+    /// `k` if there are fewer than `k` distinct hashes).
+    fn kmv_sketch(self, k: usize) -> Vec<u64> {
+        let mut sketch = KmvSketch::new(k);
+        self.for_each(|h| sketch.update(h));
+        sketch.finish()
+    }
+}
+
+impl<T: Iterator<Item = u64>> SketchExt for T {}
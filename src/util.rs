@@ -1,5 +1,9 @@
 //! Small utilities: splitmix64 mixing and helpers.
 
+use alloc::vec;
+use alloc::vec::Vec;
+use smallvec::SmallVec;
+
 #[inline]
 pub fn splitmix64(mut x: u64) -> u64 {
     // From Steele et al. (SplitMix64) – deterministic across platforms.
@@ -10,6 +14,28 @@ pub fn splitmix64(mut x: u64) -> u64 {
     z ^ (z >> 31)
 }
 
+/// Deterministic generator iterating [`splitmix64`]: each `next_u64` returns
+/// `state = splitmix64(state)`. This is the chain the crate itself uses to
+/// derive per-hash seeds, so `SplitMix64Rng::new(seed0)` yields exactly the
+/// permutation seeds of `MinHash::new(n, seed0)`, and test fixtures built
+/// from it are reproducible without `rand`. Not for cryptographic use.
+#[derive(Clone, Debug)]
+pub struct SplitMix64Rng {
+    state: u64,
+}
+
+impl SplitMix64Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = splitmix64(self.state);
+        self.state
+    }
+}
+
 #[inline]
 pub fn mix_with_seed(x: u64, seed: u64) -> u64 {
     // Deterministic "permutation": feed x^seed through splitmix64.
@@ -26,3 +52,308 @@ pub fn hash_band(chunk: &[u64], seed: u64) -> u64 {
     }
     acc
 }
+
+/// [`hash_band`] over 32-bit rows (e.g. `minhash::MinHash32` slots), folding
+/// each as its zero-extension: `hash_band_u32(c, seed)` equals `hash_band` of
+/// the widened chunk, without building it.
+#[inline]
+pub fn hash_band_u32(chunk: &[u32], seed: u64) -> u64 {
+    let mut acc = seed ^ 0xDEADBEEFDEADBEEF;
+    for &v in chunk {
+        acc = splitmix64(acc ^ u64::from(v));
+    }
+    acc
+}
+
+/// Element and band mixing function used by `MinHash` and `LshIndex`, so
+/// another hash (xxhash, wyhash, ...) can be swapped in for splitmix64.
+/// Sketches and indexes are generic over it with [`SplitMix64`] as the
+/// default, so the default path is monomorphized to exactly the built-in
+/// functions.
+///
+/// The mixer is not recorded in signatures or serialized indexes: build and
+/// query with the same mixer, or signatures and band keys silently stop
+/// matching. Seed derivation (per-hash and per-band seeds) always uses
+/// splitmix64, whatever the mixer.
+pub trait Mixer {
+    /// Seeded mix of one value; the seed selects the permutation.
+    fn mix(&self, x: u64, seed: u64) -> u64;
+
+    /// Fold a band's rows into one key. The default chains `mix` like
+    /// [`hash_band`] chains splitmix64.
+    #[inline]
+    fn fold(&self, chunk: &[u64], seed: u64) -> u64 {
+        let mut acc = seed ^ 0xDEADBEEFDEADBEEF;
+        for &v in chunk {
+            acc = self.mix(v, acc);
+        }
+        acc
+    }
+
+    /// `fold` of 32-bit rows, which must equal `fold` of the zero-extended
+    /// chunk. The default widens the chunk on the stack (on the heap only
+    /// beyond 16 rows) and calls `fold`; override to fold u32 rows directly.
+    #[inline]
+    fn fold_u32(&self, chunk: &[u32], seed: u64) -> u64 {
+        let wide: SmallVec<[u64; 16]> = chunk.iter().map(|&v| u64::from(v)).collect();
+        self.fold(&wide, seed)
+    }
+
+    /// Four `fold`s at once (`out[i] == fold(chunks[i], seeds[i])`); override
+    /// to vectorize.
+    #[inline]
+    fn fold_x4(&self, chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+        [0, 1, 2, 3].map(|i| self.fold(chunks[i], seeds[i]))
+    }
+}
+
+/// The built-in [`Mixer`]: [`mix_with_seed`], [`hash_band`],
+/// [`hash_band_u32`] and [`hash_band_x4`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitMix64;
+
+impl Mixer for SplitMix64 {
+    #[inline]
+    fn mix(&self, x: u64, seed: u64) -> u64 {
+        mix_with_seed(x, seed)
+    }
+
+    #[inline]
+    fn fold(&self, chunk: &[u64], seed: u64) -> u64 {
+        hash_band(chunk, seed)
+    }
+
+    #[inline]
+    fn fold_u32(&self, chunk: &[u32], seed: u64) -> u64 {
+        hash_band_u32(chunk, seed)
+    }
+
+    #[inline]
+    fn fold_x4(&self, chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+        hash_band_x4(chunks, seeds)
+    }
+}
+
+/// [`hash_band`] over a signature stored as bytes: each 8-byte group is read
+/// as a **little-endian** u64, whatever the host byte order, so
+/// `hash_band_bytes(le_bytes, seed) == hash_band(values, seed)` everywhere.
+///
+/// `LshIndex` keys already come from u64 values, not bytes, and so are
+/// identical on big- and little-endian hosts; this is for signatures that
+/// travel as bytes, which must be written little-endian (`to_le_bytes`).
+///
+/// # Panics
+/// If `bytes.len()` is not a multiple of 8.
+#[inline]
+pub fn hash_band_bytes(bytes: &[u8], seed: u64) -> u64 {
+    assert!(
+        bytes.len() % 8 == 0,
+        "band bytes must be whole little-endian u64 words"
+    );
+    let mut acc = seed ^ 0xDEADBEEFDEADBEEF;
+    for w in bytes.chunks_exact(8) {
+        acc = splitmix64(acc ^ u64::from_le_bytes(w.try_into().expect("8-byte chunk")));
+    }
+    acc
+}
+
+/// Four independent [`hash_band`] folds computed side by side:
+/// `out[i] == hash_band(chunks[i], seeds[i])` for every lane.
+///
+/// Each lane runs exactly the scalar chain, so keys are identical with or
+/// without the `simd` feature and on every architecture; only the latency of
+/// the serial splitmix64 chain is hidden. All chunks must have equal length.
+/// With `simd` on x86_64, lanes run in AVX2 registers when the CPU supports
+/// it. Elsewhere the lanes are interleaved in scalar code, which also covers
+/// NEON targets: NEON has no 64-bit lane multiply to beat it with.
+#[inline]
+pub fn hash_band_x4(chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+    let rows = chunks[0].len();
+    assert!(
+        chunks.iter().all(|c| c.len() == rows),
+        "hash_band_x4 chunks must have equal length"
+    );
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("avx2") {
+        // SAFETY: AVX2 support was just checked at runtime.
+        return unsafe { avx2::hash_band_x4(chunks, seeds) };
+    }
+    hash_band_x4_scalar(chunks, seeds)
+}
+
+#[inline]
+fn hash_band_x4_scalar(chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+    let mut acc = seeds.map(|s| s ^ 0xDEADBEEFDEADBEEF);
+    for j in 0..chunks[0].len() {
+        for (a, c) in acc.iter_mut().zip(&chunks) {
+            *a = splitmix64(*a ^ c[j]);
+        }
+    }
+    acc
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Low 64 bits of a lane-wise 64x64 product; AVX2 only multiplies 32-bit halves.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
+    unsafe fn mul64(a: __m256i, b: __m256i) -> __m256i {
+        unsafe {
+            let lo = _mm256_mul_epu32(a, b);
+            let cross = _mm256_add_epi64(
+                _mm256_mul_epu32(_mm256_srli_epi64::<32>(a), b),
+                _mm256_mul_epu32(a, _mm256_srli_epi64::<32>(b)),
+            );
+            _mm256_add_epi64(lo, _mm256_slli_epi64::<32>(cross))
+        }
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
+    unsafe fn splitmix64(x: __m256i) -> __m256i {
+        unsafe {
+            let z = _mm256_add_epi64(x, _mm256_set1_epi64x(0x9E3779B97F4A7C15u64 as i64));
+            let z = _mm256_xor_si256(z, _mm256_srli_epi64::<30>(z));
+            let z = mul64(z, _mm256_set1_epi64x(0xBF58476D1CE4E5B9u64 as i64));
+            let z = _mm256_xor_si256(z, _mm256_srli_epi64::<27>(z));
+            let z = mul64(z, _mm256_set1_epi64x(0x94D049BB133111EBu64 as i64));
+            _mm256_xor_si256(z, _mm256_srli_epi64::<31>(z))
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[allow(unused_unsafe)]
+    pub(super) unsafe fn hash_band_x4(chunks: [&[u64]; 4], seeds: [u64; 4]) -> [u64; 4] {
+        let lane = |x: [u64; 4]| unsafe {
+            _mm256_set_epi64x(x[3] as i64, x[2] as i64, x[1] as i64, x[0] as i64)
+        };
+        unsafe {
+            let mut acc = _mm256_xor_si256(
+                lane(seeds),
+                _mm256_set1_epi64x(0xDEADBEEFDEADBEEFu64 as i64),
+            );
+            let [c0, c1, c2, c3] = chunks;
+            let rows = c0.iter().zip(c1).zip(c2).zip(c3);
+            for (((&r0, &r1), &r2), &r3) in rows {
+                let v = lane([r0, r1, r2, r3]);
+                acc = splitmix64(_mm256_xor_si256(acc, v));
+            }
+            let mut out = [0u64; 4];
+            _mm256_storeu_si256(out.as_mut_ptr().cast(), acc);
+            out
+        }
+    }
+}
+
+/// Hash a k-mer's bytes to a u64 suitable for `MinHash`/`KmvSketch::update`.
+///
+/// Bytes are read as little-endian 8-byte words (the last one zero-padded)
+/// and folded through splitmix64 together with the length, so results are
+/// identical on every platform. The hash is case-sensitive: normalize case
+/// before hashing if your input mixes it.
+#[inline]
+pub fn hash_kmer(bytes: &[u8], seed: u64) -> u64 {
+    let mut acc = splitmix64(seed ^ bytes.len() as u64);
+    let mut chunks = bytes.chunks_exact(8);
+    for c in &mut chunks {
+        acc = splitmix64(acc ^ u64::from_le_bytes(c.try_into().expect("8-byte chunk")));
+    }
+    let rem = chunks.remainder();
+    if !rem.is_empty() {
+        let mut buf = [0u8; 8];
+        buf[..rem.len()].copy_from_slice(rem);
+        acc = splitmix64(acc ^ u64::from_le_bytes(buf));
+    }
+    acc
+}
+
+/// Strand-independent k-mer hash: the minimum of `hash_kmer` over the k-mer
+/// and its reverse complement (supplied by the caller, e.g. from the same
+/// sequence scan), so a k-mer and its reverse complement hash identically.
+#[inline]
+pub fn canonical_kmer_hash(kmer: &[u8], revcomp: &[u8], seed: u64) -> u64 {
+    hash_kmer(kmer, seed).min(hash_kmer(revcomp, seed))
+}
+
+/// Rolling hash over the last `k` bytes of a stream, for feeding every
+/// overlapping k-mer of a sequence into a sketch in O(1) per base.
+///
+/// The window is a polynomial (Rabin–Karp) hash mod 2^64, whitened with
+/// splitmix64 and the seed. It is not interchangeable with [`hash_kmer`]:
+/// sketches must be built with one or the other consistently.
+///
+/// ```rust
+/// use kira_cdh_compat_lsh::{kmv::KmvSketch, util::RollingKmerHasher};
+///
+/// let mut roller = RollingKmerHasher::new(21, 0);
+/// let mut sketch = KmvSketch::new(128);
+/// for &base in b"ACGTTGCAACGTACGATCGATCGGATCGATTACG" {
+///     if let Some(h) = roller.roll(base) {
+///         sketch.update(h);
+///     }
+/// }
+/// roller.reset(); // before the next sequence
+/// ```
+pub struct RollingKmerHasher {
+    k: usize,
+    seed: u64,
+    // BASE^(k-1), the weight of the byte leaving the window.
+    out_weight: u64,
+    window: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    poly: u64,
+}
+
+impl RollingKmerHasher {
+    const BASE: u64 = 0x100000001B3; // odd, so multiplication is invertible mod 2^64
+
+    pub fn new(k: usize, seed: u64) -> Self {
+        assert!(k > 0, "k must be non-zero");
+        let mut out_weight = 1u64;
+        for _ in 1..k {
+            out_weight = out_weight.wrapping_mul(Self::BASE);
+        }
+        Self {
+            k,
+            seed,
+            out_weight,
+            window: vec![0; k],
+            pos: 0,
+            filled: 0,
+            poly: 0,
+        }
+    }
+
+    /// Push the next byte. Returns the hash of the current k-byte window, or
+    /// `None` while the first `k` bytes are still being filled in.
+    #[inline]
+    pub fn roll(&mut self, incoming: u8) -> Option<u64> {
+        if self.filled == self.k {
+            let outgoing = self.window[self.pos] as u64 + 1;
+            self.poly = self
+                .poly
+                .wrapping_sub(outgoing.wrapping_mul(self.out_weight));
+        } else {
+            self.filled += 1;
+        }
+        self.window[self.pos] = incoming;
+        self.pos = (self.pos + 1) % self.k;
+        self.poly = self
+            .poly
+            .wrapping_mul(Self::BASE)
+            .wrapping_add(incoming as u64 + 1);
+        (self.filled == self.k).then(|| mix_with_seed(self.poly, self.seed))
+    }
+
+    /// Forget the current window, e.g. between sequences or at an `N` run.
+    pub fn reset(&mut self) {
+        self.pos = 0;
+        self.filled = 0;
+        self.poly = 0;
+    }
+}
@@ -1,8 +1,13 @@
 use kira_cdh_compat_lsh::{
     kmv::KmvSketch,
-    lsh::{LshIndex, LshParams},
-    minhash::MinHash,
-    sketch::jaccard_from_signatures,
+    lsh::{LshIndex, LshParams, LshQueryBuffer},
+    minhash::{MinHash, OnePermutationMinHash, SuperMinHash, b_bit_compress},
+    sketch::{
+        containment_from_signatures, containment_kmv, dice_from_signatures, jaccard_b_bit,
+        jaccard_from_signatures, jaccard_kmv, jaccard_kmv_robust, jaccard_sorted_sets,
+        kmv_coverage, kmv_intersection_cardinality, kmv_union_cardinality, minhash_distance,
+        minhash_similarity, overlap_coefficient, try_jaccard_from_signatures,
+    },
 };
 
 #[test]
@@ -15,6 +20,155 @@ fn minhash_basic() {
     assert_eq!(sig.len(), 64);
 }
 
+#[test]
+fn minhash_update_all_matches_update() {
+    let xs: Vec<u64> = (0..500).map(|i| i * 31 + 7).collect();
+    let mut a = MinHash::new(64, 99);
+    let mut b = MinHash::new(64, 99);
+    for &x in &xs {
+        a.update(x);
+    }
+    b.update_all(&xs[..200]);
+    b.update_all(&xs[200..]);
+    assert_eq!(a.finish(), b.finish());
+}
+
+#[test]
+fn minhash_merge() {
+    let mut whole = MinHash::new(32, 7);
+    let mut left = MinHash::new(32, 7);
+    let mut right = MinHash::new(32, 7);
+    for x in 0u64..100 {
+        whole.update(x);
+        if x % 2 == 0 {
+            left.update(x);
+        } else {
+            right.update(x);
+        }
+    }
+    left.merge(&right).unwrap();
+    assert_eq!(left.finish(), whole.finish());
+
+    let mut other_seed = MinHash::new(32, 8);
+    assert!(other_seed.merge(&MinHash::new(32, 7)).is_err());
+}
+
+#[test]
+fn one_permutation_minhash() {
+    let build = |range: std::ops::Range<u64>| {
+        let mut oph = OnePermutationMinHash::new(128, 5);
+        for x in range {
+            oph.update(x);
+        }
+        oph.finish()
+    };
+    // Few elements: densification must still fill every slot.
+    let sparse = build(0..10);
+    assert_eq!(sparse.len(), 128);
+    assert!(sparse.iter().all(|&v| v != u64::MAX));
+
+    // |A ∩ B| / |A ∪ B| = 8000 / 12000
+    let a = build(0..10_000);
+    let b = build(2_000..12_000);
+    let j = jaccard_from_signatures(&a, &b);
+    assert!((j - 2.0 / 3.0).abs() < 0.15, "j = {j}");
+    assert_eq!(build(0..0), vec![u64::MAX; 128]);
+}
+
+#[test]
+fn minhash_serialize_roundtrip() {
+    let mut mh = MinHash::new(16, 42);
+    for x in 0u64..50 {
+        mh.update(x);
+    }
+    let bytes = mh.serialize();
+    let mut restored = MinHash::deserialize(&bytes).unwrap();
+    assert_eq!(restored.seed_fingerprint(), mh.seed_fingerprint());
+    let mut more = MinHash::new(16, 42);
+    for x in 50u64..80 {
+        more.update(x);
+    }
+    restored.merge(&more).unwrap();
+    mh.merge(&more).unwrap();
+    assert_eq!(restored.finish(), mh.finish());
+
+    let mut tampered = bytes.clone();
+    tampered[17] ^= 1; // fingerprint word
+    assert!(MinHash::deserialize(&tampered).is_err());
+    assert!(MinHash::deserialize(&bytes[..bytes.len() - 8]).is_err());
+    assert_ne!(
+        MinHash::new(16, 1).seed_fingerprint(),
+        MinHash::new(16, 2).seed_fingerprint()
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn weighted_minhash_estimates_weighted_jaccard() {
+    use kira_cdh_compat_lsh::minhash::WeightedMinHash;
+    let mut a = WeightedMinHash::new(512, 11);
+    let mut b = WeightedMinHash::new(512, 11);
+    // Same elements, B has every weight doubled: Σmin / Σmax = 1/2.
+    for e in 0u64..300 {
+        let w = 1.0 + (e % 5) as f64;
+        a.update(e, w);
+        b.update(e, 2.0 * w);
+    }
+    a.update(999, 0.0);
+    let (a, b) = (a.finish(), b.finish());
+    let j = jaccard_from_signatures(&a, &b);
+    assert!((j - 0.5).abs() < 0.08, "j = {j}");
+    assert_eq!(jaccard_from_signatures(&a, &a), 1.0);
+}
+
+#[test]
+fn superminhash_has_lower_variance_than_minhash() {
+    let m = 64;
+    // |A ∩ B| = 40, |A ∪ B| = 80 -> J = 0.5
+    let trials = 300u64;
+    let (mut se_classic, mut se_super) = (0.0, 0.0);
+    for t in 0..trials {
+        let base = t * 1_000_000;
+        let (mut ca, mut cb) = (MinHash::new(m, t), MinHash::new(m, t));
+        let (mut sa, mut sb) = (SuperMinHash::new(m, t), SuperMinHash::new(m, t));
+        for x in base..base + 60 {
+            ca.update(x);
+            sa.update(x);
+        }
+        for x in base + 20..base + 80 {
+            cb.update(x);
+            sb.update(x);
+        }
+        let jc = jaccard_from_signatures(&ca.finish(), &cb.finish());
+        let js = jaccard_from_signatures(&sa.finish(), &sb.finish());
+        se_classic += (jc - 0.5) * (jc - 0.5);
+        se_super += (js - 0.5) * (js - 0.5);
+    }
+    let (mse_classic, mse_super) = (se_classic / trials as f64, se_super / trials as f64);
+    assert!(
+        mse_super < 0.8 * mse_classic,
+        "super={mse_super} classic={mse_classic}"
+    );
+}
+
+#[test]
+fn sketch_clones_match_origin() {
+    let mut mh = MinHash::new(32, 1);
+    let mut kmv = KmvSketch::new(32);
+    for x in 0u64..100 {
+        mh.update(x);
+        kmv.update(x * 7919);
+    }
+    let (mh_snap, kmv_snap) = (mh.clone(), kmv.clone());
+    assert_eq!(mh_snap.finish(), mh.clone().finish());
+    assert_eq!(kmv_snap.finish(), kmv.clone().finish());
+
+    // The snapshot is independent of later updates to the origin.
+    let prefix = kmv.clone();
+    kmv.update(1);
+    assert_ne!(prefix.finish(), kmv.finish());
+}
+
 #[test]
 fn kmv_basic() {
     let mut k = KmvSketch::new(64);
@@ -26,6 +180,139 @@ fn kmv_basic() {
     assert!(sig.windows(2).all(|w| w[0] <= w[1]));
 }
 
+#[test]
+fn kmv_keeps_smallest_distinct() {
+    let mut k = KmvSketch::new(3);
+    for x in [50u64, 7, 7, 90, 3, 12, 3, 1] {
+        k.update(x);
+    }
+    assert_eq!(k.finish(), vec![1, 3, 7]);
+}
+
+#[test]
+fn kmv_dedup_survives_rebuilds() {
+    use kira_cdh_compat_lsh::kmv::AdaptiveKmvSketch;
+    // Values already held must stay rejected after every way of rebuilding
+    // the kept set.
+    let mut resumed = KmvSketch::from_signature(4, &[2, 4, 6]).unwrap();
+    let mut decoded = KmvSketch::deserialize(&resumed.serialize()).unwrap();
+    let mut merged = KmvSketch::new(4);
+    merged.merge(&resumed).unwrap();
+    for s in [&mut resumed, &mut decoded, &mut merged] {
+        for x in [4u64, 2, 6, 5, 4, 1, 5] {
+            s.update(x);
+        }
+        assert_eq!(s.clone().finish(), vec![1, 2, 4, 5]);
+    }
+    merged.reset();
+    [9u64, 9, 8].iter().for_each(|&x| merged.update(x));
+    assert_eq!(merged.finish(), vec![8, 9]);
+
+    let mut adaptive = AdaptiveKmvSketch::new(1, 4);
+    for x in [7u64, 7, 3, 7, 3, 9, 1, 1, 0] {
+        adaptive.update(x);
+    }
+    assert_eq!(adaptive.finish(), vec![0, 1, 3, 7]);
+}
+
+#[test]
+fn kmv_try_new_rejects_zero_k() {
+    assert!(matches!(
+        KmvSketch::try_new(0),
+        Err(kira_cdh_compat_lsh::errors::SketchError::ZeroK)
+    ));
+    assert!(KmvSketch::try_new(1).is_ok());
+}
+
+#[test]
+fn kmv_len_and_full() {
+    let mut k = KmvSketch::new(3);
+    assert!(k.is_empty());
+    k.update(5);
+    k.update(5);
+    assert_eq!(k.len(), 1);
+    assert!(!k.is_full());
+    k.update(6);
+    k.update(7);
+    assert!(k.is_full());
+    k.update(1);
+    assert_eq!(k.len(), 3);
+}
+
+#[test]
+fn kmv_merge() {
+    let mut a = KmvSketch::new(4);
+    let mut b = KmvSketch::new(4);
+    for x in [10u64, 3, 7] {
+        a.update(x);
+    }
+    for x in [3u64, 1, 20] {
+        b.update(x);
+    }
+    a.merge(&b).unwrap();
+    assert_eq!(a.finish(), vec![1, 3, 7, 10]);
+
+    let mut acc = KmvSketch::new(4);
+    for s in [KmvSketch::new(4), KmvSketch::new(4)] {
+        s.merge_into(&mut acc).unwrap();
+    }
+    assert!(acc.finish().is_empty());
+
+    let mut c = KmvSketch::new(8);
+    assert!(c.merge(&KmvSketch::new(4)).is_err());
+}
+
+#[test]
+fn kmv_from_signature_resumes() {
+    let mut whole = KmvSketch::new(8);
+    let mut first = KmvSketch::new(8);
+    for x in 0u64..40 {
+        let h = kira_cdh_compat_lsh::util::splitmix64(x);
+        whole.update(h);
+        if x < 20 {
+            first.update(h);
+        }
+    }
+    let mut resumed = KmvSketch::from_signature(8, &first.finish()).unwrap();
+    for x in 20u64..40 {
+        resumed.update(kira_cdh_compat_lsh::util::splitmix64(x));
+    }
+    assert_eq!(resumed.finish(), whole.finish());
+
+    assert!(KmvSketch::from_signature(2, &[1, 2, 3]).is_err());
+    assert!(KmvSketch::from_signature(4, &[1, 1]).is_err());
+    assert!(KmvSketch::from_signature(4, &[3, 2]).is_err());
+}
+
+#[test]
+fn kmv_serialize_roundtrip() {
+    let mut full = KmvSketch::new(4);
+    let mut under = KmvSketch::new(4);
+    for x in [9u64, 4, 30, 2, 17, 8] {
+        full.update(x);
+    }
+    under.update(5);
+
+    for sketch in [full, under] {
+        let bytes = sketch.serialize();
+        assert_eq!(bytes[0], 1);
+        let mut restored = KmvSketch::deserialize(&bytes).unwrap();
+        let mut original = sketch;
+        for x in [1u64, 6, 100] {
+            original.update(x);
+            restored.update(x);
+        }
+        assert_eq!(restored.finish(), original.finish());
+    }
+
+    let bytes = KmvSketch::new(2).serialize();
+    assert!(KmvSketch::deserialize(&[]).is_err());
+    assert!(KmvSketch::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    let mut bad_version = bytes.clone();
+    bad_version[0] = 99;
+    assert!(KmvSketch::deserialize(&bad_version).is_err());
+}
+
 #[test]
 fn lsh_query() {
     let params = LshParams::new(32, 4).unwrap();
@@ -44,6 +331,454 @@ fn lsh_query() {
     assert!(cands.iter().any(|(id, _)| *id == 1));
 }
 
+#[cfg(feature = "std")]
+#[test]
+fn lsh_params_for_threshold() {
+    let p = LshParams::for_threshold(128, 0.9).unwrap();
+    assert_eq!(p.signature_len(), 128);
+    // Check that no other factorization sits closer to the target.
+    let mid = |p: &LshParams| {
+        let mut lo = 0.0;
+        let mut hi = 1.0;
+        for _ in 0..60 {
+            let m = (lo + hi) / 2.0;
+            if p.probability_at(m) < 0.5 {
+                lo = m
+            } else {
+                hi = m
+            }
+        }
+        lo
+    };
+    let gap = (mid(&p) - 0.9).abs();
+    for r in [1usize, 2, 4, 8, 16, 32, 64, 128] {
+        let q = LshParams::new(128 / r, r).unwrap();
+        assert!(gap <= (mid(&q) - 0.9).abs() + 1e-9);
+    }
+    assert!(p.probability_at(0.99) > p.probability_at(0.5));
+
+    assert!(LshParams::for_threshold(0, 0.9).is_err());
+    assert!(LshParams::for_threshold(128, 1.5).is_err());
+}
+
+#[test]
+fn lsh_remove() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s1: Vec<u64> = (0..16).collect();
+    idx.insert(0, &s1).unwrap();
+    idx.insert(1, &s1).unwrap();
+
+    idx.remove(0, &s1).unwrap();
+    let cands = idx.query_candidates(&s1, 1);
+    assert_eq!(cands, vec![(1, 8)]);
+
+    idx.remove(1, &s1).unwrap();
+    assert!(idx.query_candidates(&s1, 1).is_empty());
+}
+
+#[test]
+fn lsh_remove_with_mismatched_band_zero_changes_nothing() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params_storing(params);
+    let s1: Vec<u64> = (0..16).collect();
+    idx.insert(0, &s1).unwrap();
+
+    // Same rows as `s1` except in band 0: bands 1..8 would match.
+    let mut perturbed = s1.clone();
+    perturbed[0] = 1000;
+    idx.remove(0, &perturbed).unwrap();
+    assert_eq!(idx.len(), 1);
+    assert!(idx.contains(0));
+    assert_eq!(idx.query_candidates(&s1, 1), vec![(0, 8)]);
+    assert_eq!(idx.query_by_id(0, 1).unwrap(), vec![(0, 8)]);
+
+    idx.remove(0, &s1).unwrap();
+    assert!(idx.is_empty());
+    assert!(idx.query_candidates(&s1, 1).is_empty());
+}
+
+#[test]
+fn lsh_remove_keeps_stored_signature_of_live_duplicates() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params_storing(params);
+    let s1: Vec<u64> = (0..16).collect();
+    let s2: Vec<u64> = (100..116).collect();
+    idx.insert(0, &s1).unwrap();
+    idx.insert(0, &s2).unwrap();
+
+    // Nothing matches this signature: the entry and its stored copy stay.
+    idx.remove(0, &(200..216).collect::<Vec<u64>>()).unwrap();
+    assert_eq!(idx.len(), 2);
+    assert!(idx.query_by_id(0, 1).is_some());
+
+    idx.remove(0, &s1).unwrap();
+    assert_eq!(idx.len(), 1);
+    assert!(idx.contains(0));
+    assert!(idx.query_by_id(0, 1).is_some());
+    assert_eq!(idx.refine(&s2, &[(0, 8)]).len(), 1);
+
+    idx.remove(0, &s2).unwrap();
+    assert!(!idx.contains(0));
+    assert!(idx.query_by_id(0, 1).is_none());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn lsh_insert_bulk_parallel_matches_serial() {
+    let params = LshParams::new(16, 2).unwrap();
+    let items: Vec<(u32, Vec<u64>)> = (0..200u32)
+        .map(|i| (i, (0..32).map(|j| (i as u64 % 7) * 100 + j % 3).collect()))
+        .collect();
+    let mut serial = LshIndex::with_params(params.clone());
+    for (id, sig) in &items {
+        serial.insert(*id, sig).unwrap();
+    }
+    let mut bulk = LshIndex::with_params(params);
+    bulk.insert_bulk_parallel(&items).unwrap();
+    for (_, sig) in &items {
+        assert_eq!(
+            bulk.query_candidates(sig, 1),
+            serial.query_candidates(sig, 1)
+        );
+    }
+    assert!(bulk.insert_bulk_parallel(&[(0, vec![1; 4])]).is_err());
+}
+
+#[test]
+fn lsh_query_candidates_into() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..16).collect();
+    let mut t = s.clone();
+    t[0] = 99;
+    idx.insert(0u32, &s).unwrap();
+    idx.insert(1, &t).unwrap();
+    let mut seen = Vec::new();
+    idx.query_candidates_into(&s, 8, |id, c| seen.push((id, c)));
+    assert_eq!(seen, vec![(0, 8)]);
+    seen.clear();
+    idx.query_candidates_into(&s, 1, |id, c| seen.push((id, c)));
+    seen.sort_unstable();
+    assert_eq!(seen, vec![(0, 8), (1, 7)]);
+}
+
+#[test]
+fn lsh_query_excluding() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..16).collect();
+    for id in 0u32..3 {
+        idx.insert(id, &s).unwrap();
+    }
+    assert_eq!(
+        idx.query_candidates_excluding(&s, 1, 1),
+        vec![(0, 8), (2, 8)]
+    );
+}
+
+#[test]
+fn lsh_candidate_pairs() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..16).collect();
+    let mut t = s.clone();
+    t[0] = 100;
+    idx.insert(2u32, &s).unwrap();
+    idx.insert(0, &s).unwrap();
+    idx.insert(1, &t).unwrap();
+    idx.insert(9, &[7; 16]).unwrap();
+
+    assert_eq!(
+        idx.candidate_pairs(1),
+        vec![(0, 2, 8), (0, 1, 7), (1, 2, 7)]
+    );
+    assert_eq!(idx.candidate_pairs(8), vec![(0, 2, 8)]);
+    // Buckets of size 3 (shared by all three) are skipped; only band 0 remains.
+    assert_eq!(idx.candidate_pairs_capped(1, 2), vec![(0, 2, 1)]);
+}
+
+#[test]
+fn lsh_multiprobe_query() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let q: Vec<u64> = vec![5, 3, 10, 11, 20, 21, 30, 31];
+    // Band 0 has row 1 replaced by the band's second-smallest value (5); no
+    // other band matches, so only a substitution probe finds it.
+    let near: Vec<u64> = vec![5, 5, 110, 111, 120, 121, 130, 131];
+    idx.insert(0u32, &q).unwrap();
+    idx.insert(1, &near).unwrap();
+    for i in 2..40u32 {
+        let other: Vec<u64> = (0..8).map(|r| u64::from(i) * 1000 + r).collect();
+        idx.insert(i, &other).unwrap();
+    }
+
+    assert_eq!(idx.query_candidates(&q, 1), vec![(0, 4)]);
+    for min in [1, 2, 4] {
+        assert_eq!(
+            idx.query_candidates_multiprobe(&q, min, 0),
+            idx.query_candidates(&q, min)
+        );
+    }
+    let hits = idx.query_candidates_multiprobe(&q, 1, 1);
+    assert_eq!(hits, vec![(0, 4), (1, 1)]);
+    // Extra (bit-flip) probes never count a band twice.
+    let hits = idx.query_candidates_multiprobe(&q, 1, 8);
+    assert_eq!(hits[0], (0, 4));
+    assert!(hits.iter().all(|&(_, c)| c <= 4));
+}
+
+#[test]
+fn lsh_query_top_k() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let q: Vec<u64> = (0..16).collect();
+    for id in 0u32..20 {
+        let mut s = q.clone();
+        // id % 8 rows differ -> fewer collisions for larger remainders
+        for r in 0..(id % 8) as usize {
+            s[r * 2] = 1000 + id as u64;
+        }
+        idx.insert(id, &s).unwrap();
+    }
+    let all = idx.query_candidates(&q, 1);
+    for k in [0usize, 1, 5, 13, 100] {
+        let top = idx.query_top_k(&q, k);
+        assert_eq!(top, all[..k.min(all.len())].to_vec());
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn lsh_query_with_jaccard() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let q: Vec<u64> = (0..8).collect();
+    let mut half = q.clone();
+    half[0] = 100;
+    half[2] = 100;
+    idx.insert(0u32, &q).unwrap();
+    idx.insert(1, &half).unwrap();
+    let hits = idx.query_with_jaccard(&q, 1);
+    assert_eq!(hits[0], (0, 4, 1.0));
+    assert_eq!(hits[1].0, 1);
+    assert_eq!(hits[1].1, 2);
+    assert!((hits[1].2 - 0.5f64.sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn lsh_band_keys_do_not_alias() {
+    let params = LshParams::new(32, 4).unwrap();
+    let mut idx = LshIndex::with_seed(params, 0);
+    // All-equal signatures are the worst case for correlated band seeds:
+    // every band folds the same rows, so only the seed tells bands apart.
+    for v in 0u64..64 {
+        idx.insert(v as u32, &[v; 128]).unwrap();
+    }
+    for v in 0u64..64 {
+        // Each signature collides only with itself, in every band.
+        assert_eq!(idx.query_candidates(&[v; 128], 1), vec![(v as u32, 32)]);
+    }
+    assert!(idx.candidate_pairs(1).is_empty());
+
+    // Pinned `splitmix64(seed ^ splitmix64(b))` for seed 0; the former XOR
+    // derivation would give bands the seeds 0, 1, 2, 3.
+    assert_eq!(
+        idx.band_seeds()[..4],
+        [
+            0xA706DD2F4D197E6F,
+            0x5E41AB087439611E,
+            0x64684C4F0FD784B4,
+            0xBCCDFD9C96A18897,
+        ]
+    );
+}
+
+#[test]
+fn lsh_explicit_seed() {
+    let params = LshParams::new(8, 2).unwrap();
+    let default_idx: LshIndex = LshIndex::with_params(params.clone());
+    let a: LshIndex = LshIndex::with_params_and_seed(params.clone(), 1);
+    let b: LshIndex = LshIndex::with_params_and_seed(params.clone(), 2);
+    assert_eq!(a.seed(), 1);
+    assert_eq!(b.seed(), 2);
+    assert_ne!(default_idx.seed(), a.seed());
+    let alias: LshIndex = LshIndex::with_seed(params, 1);
+    assert_eq!(alias.band_seeds(), a.band_seeds());
+}
+
+#[test]
+fn lsh_stats() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let empty = idx.stats();
+    assert_eq!(empty.total_ids, 0);
+    assert_eq!(empty.mean_bucket_size, 0.0);
+
+    let s: Vec<u64> = (0..8).collect();
+    let mut t = s.clone();
+    t[0] = 99;
+    idx.insert(0u32, &s).unwrap();
+    idx.insert(1, &s).unwrap();
+    idx.insert(2, &t).unwrap();
+    let st = idx.stats();
+    assert_eq!(st.buckets_per_band, vec![2, 1, 1, 1]);
+    assert_eq!(st.total_ids, 12);
+    assert_eq!(st.max_bucket_size, 3);
+    assert!((st.mean_bucket_size - 12.0 / 5.0).abs() < 1e-12);
+    assert!(st.estimated_bytes > 0);
+    assert!(st.to_string().starts_with("bands=4 buckets=5"));
+}
+
+#[test]
+fn lsh_max_bucket_size_skips_mega_buckets() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let q: Vec<u64> = (0..8).collect();
+    // Ten IDs share band 0 with the query; only ID 0 matches elsewhere.
+    for id in 0u32..10 {
+        let mut s = q.clone();
+        if id != 0 {
+            for v in &mut s[2..] {
+                *v += 100 * id as u64;
+            }
+        }
+        idx.insert(id, &s).unwrap();
+    }
+    assert_eq!(idx.query_candidates(&q, 1).len(), 10);
+
+    idx.set_max_bucket_size(Some(5));
+    assert_eq!(idx.max_bucket_size(), Some(5));
+    assert_eq!(idx.query_candidates(&q, 1), vec![(0, 3)]);
+    assert!(idx.candidate_pairs(1).is_empty());
+    let st = idx.stats();
+    assert_eq!(st.oversized_buckets, 1);
+    assert_eq!(st.skipped_bucket_lookups, 1);
+}
+
+#[test]
+fn lsh_clear_and_len() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params_storing(params);
+    let s: Vec<u64> = (0..8).collect();
+    assert!(idx.is_empty());
+    idx.insert(0u32, &s).unwrap();
+    idx.insert(1, &s).unwrap();
+    assert_eq!(idx.len(), 2);
+    idx.remove(1, &s).unwrap();
+    idx.remove(5, &s).unwrap();
+    assert_eq!(idx.len(), 1);
+
+    idx.clear();
+    assert!(idx.is_empty());
+    assert!(idx.query_candidates(&s, 1).is_empty());
+    assert!(idx.query_by_id(0, 1).is_none());
+    idx.insert(2, &s).unwrap();
+    assert_eq!(idx.query_candidates(&s, 1), vec![(2, 4)]);
+}
+
+#[test]
+fn lsh_contains_and_insert_unique() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..8).collect();
+    assert!(!idx.contains(3u32));
+    idx.insert_unique(3, &s).unwrap();
+    assert!(idx.contains(3));
+    assert!(matches!(
+        idx.insert_unique(3, &s),
+        Err(kira_cdh_compat_lsh::errors::LshError::AlreadyPresent)
+    ));
+
+    // Plain insert still allows duplicates; removal clears every copy.
+    idx.insert(3, &s).unwrap();
+    idx.remove(3, &s).unwrap();
+    assert!(!idx.contains(3));
+    assert!(idx.is_empty());
+}
+
+#[test]
+fn lsh_stored_signatures() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params_storing(params.clone());
+    let s0: Vec<u64> = (0..16).collect();
+    let mut s1 = s0.clone();
+    s1[15] = 99;
+    idx.insert(0u32, &s0).unwrap();
+    idx.insert(1, &s1).unwrap();
+
+    let cands = idx.query_by_id(0, 1).unwrap();
+    assert_eq!(cands, vec![(0, 8), (1, 7)]);
+    assert_eq!(idx.refine(&s0, &cands), vec![(0, 1.0), (1, 15.0 / 16.0)]);
+    assert!(idx.query_by_id(7, 1).is_none());
+
+    idx.remove(1, &s1).unwrap();
+    assert!(idx.query_by_id(1, 1).is_none());
+
+    let mut plain = LshIndex::with_params(params);
+    plain.insert(0u32, &s0).unwrap();
+    assert!(plain.query_by_id(0, 1).is_none());
+    assert!(plain.refine(&s0, &[(0, 8)]).is_empty());
+}
+
+#[test]
+fn lsh_u64_ids() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx: LshIndex<u64> = LshIndex::with_params(params);
+    let big = u32::MAX as u64 + 10;
+    let sig: Vec<u64> = (0..16).collect();
+    idx.insert(big, &sig).unwrap();
+    idx.insert(big + 1, &sig).unwrap();
+    assert_eq!(idx.query_candidates(&sig, 8), vec![(big, 8), (big + 1, 8)]);
+}
+
+#[test]
+fn lsh_query_batch() {
+    let params = LshParams::new(16, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sigs: Vec<Vec<u64>> = (0..50u64)
+        .map(|i| (0..32).map(|j| (i % 5) * 100 + j % 4).collect())
+        .collect();
+    for (i, sig) in sigs.iter().enumerate() {
+        idx.insert(i as u32, sig).unwrap();
+    }
+    let expected: Vec<_> = sigs.iter().map(|s| idx.query_candidates(s, 2)).collect();
+    assert_eq!(idx.query_candidates_batch(&sigs, 2), expected);
+    #[cfg(feature = "parallel")]
+    assert_eq!(idx.query_candidates_batch_parallel(&sigs, 2), expected);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn lsh_serde_roundtrip() {
+    let params = LshParams::new(16, 4).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sigs: Vec<Vec<u64>> = (0..20u64)
+        .map(|i| (0..64).map(|j| (i / 4) * 1000 + j).collect())
+        .collect();
+    for (i, sig) in sigs.iter().enumerate() {
+        idx.insert(i as u32, sig).unwrap();
+    }
+
+    let json = serde_json::to_string(&idx).unwrap();
+    let restored: LshIndex = serde_json::from_str(&json).unwrap();
+    assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    assert_eq!(restored.len(), idx.len());
+    assert!(restored.contains(19));
+
+    let mut storing = LshIndex::with_params_storing(LshParams::new(16, 4).unwrap());
+    storing.insert(3u32, &sigs[0]).unwrap();
+    let json = serde_json::to_string(&storing).unwrap();
+    let back: LshIndex = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.query_by_id(3, 1), storing.query_by_id(3, 1));
+    for sig in &sigs {
+        assert_eq!(
+            restored.query_candidates(sig, 1),
+            idx.query_candidates(sig, 1)
+        );
+    }
+}
+
 #[test]
 fn jaccard_estimate() {
     let a = vec![1u64; 128];
@@ -52,3 +787,1677 @@ fn jaccard_estimate() {
     let j = jaccard_from_signatures(&a, &b);
     assert!(j > 0.9);
 }
+
+#[test]
+fn try_jaccard_rejects_length_mismatch() {
+    let a = vec![1u64; 64];
+    let b = vec![1u64; 128];
+    assert_eq!(jaccard_from_signatures(&a, &b), 1.0);
+    assert!(try_jaccard_from_signatures(&a, &b).is_err());
+    assert_eq!(try_jaccard_from_signatures(&b, &b).unwrap(), 1.0);
+}
+
+#[test]
+fn minhash_distance_and_similarity() {
+    let a = vec![1u64, 2, 3, 4, 5];
+    let b = vec![1u64, 9, 3, 9, 5, 6, 7];
+    assert_eq!(minhash_distance(&a, &b), 2);
+    assert_eq!(minhash_similarity(&a, &b), jaccard_from_signatures(&a, &b));
+    assert_eq!(minhash_similarity(&[], &b), 0.0);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn jaccard_confidence_interval() {
+    use kira_cdh_compat_lsh::sketch::jaccard_with_ci;
+    let a: Vec<u64> = (0..100).collect();
+    let mut b = a.clone();
+    for v in &mut b[..20] {
+        *v += 1000;
+    }
+    let (j, lo, hi) = jaccard_with_ci(&a, &b, 0.95);
+    assert_eq!(j, 0.8);
+    // 1.96 * sqrt(0.8 * 0.2 / 100) = 0.0784
+    assert!((hi - j - 0.0784).abs() < 1e-3, "hi = {hi}");
+    assert!((j - lo - 0.0784).abs() < 1e-3, "lo = {lo}");
+    let (_, lo99, hi99) = jaccard_with_ci(&a, &b, 0.99);
+    assert!(lo99 < lo && hi99 > hi);
+    assert_eq!(jaccard_with_ci(&a, &a, 0.95), (1.0, 1.0, 1.0));
+}
+
+#[test]
+fn jaccard_kmv_estimate() {
+    let k = 256;
+    let mut sa = KmvSketch::new(k);
+    let mut sb = KmvSketch::new(k);
+    // |A ∩ B| = 5000, |A ∪ B| = 15000 -> J = 1/3
+    for x in 0u64..10_000 {
+        sa.update(kira_cdh_compat_lsh::util::splitmix64(x));
+    }
+    for x in 5_000u64..15_000 {
+        sb.update(kira_cdh_compat_lsh::util::splitmix64(x));
+    }
+    let (a, b) = (sa.finish(), sb.finish());
+    let j = jaccard_kmv(&a, &b, k);
+    assert!((j - 1.0 / 3.0).abs() < 0.1, "j = {j}");
+    assert_eq!(jaccard_kmv(&a, &a, k), 1.0);
+    assert_eq!(jaccard_kmv(&[], &[], k), 0.0);
+}
+
+#[test]
+fn b_bit_minhash() {
+    let sketch = |range: std::ops::Range<u64>| {
+        let mut mh = MinHash::new(1024, 3);
+        for x in range {
+            mh.update(x);
+        }
+        mh.finish()
+    };
+    // J = 600 / 1400
+    let a = sketch(0..1000);
+    let b = sketch(400..1400);
+    let full = jaccard_from_signatures(&a, &b);
+    for bits in [1u32, 2, 7, 64] {
+        let (ca, cb) = (b_bit_compress(&a, bits), b_bit_compress(&b, bits));
+        assert_eq!(ca[0], 1024);
+        let j = jaccard_b_bit(&ca, &cb, bits);
+        assert!((j - full).abs() < 0.1, "bits={bits} j={j} full={full}");
+        assert_eq!(jaccard_b_bit(&ca, &ca, bits), 1.0);
+    }
+    assert_eq!(b_bit_compress(&a, 1).len(), 1 + 1024 / 64);
+}
+
+#[test]
+fn containment_estimates() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    // Q = 0..1000 is fully inside T = 0..5000: Jaccard 0.2, containment 1.0.
+    let mut mq = MinHash::new(512, 1);
+    let mut mt = MinHash::new(512, 1);
+    let mut kq = KmvSketch::new(512);
+    let mut kt = KmvSketch::new(512);
+    for x in 0u64..5000 {
+        if x < 1000 {
+            mq.update(x);
+            kq.update(splitmix64(x));
+        }
+        mt.update(x);
+        kt.update(splitmix64(x));
+    }
+    let (mq, mt) = (mq.finish(), mt.finish());
+    let (kq, kt) = (kq.finish(), kt.finish());
+
+    assert!(containment_from_signatures(&mq, &mt) > 0.85);
+    assert!(containment_from_signatures(&mt, &mq) < 0.35);
+    assert!(containment_kmv(&kq, &kt, 512) > 0.95);
+    assert!(containment_kmv(&kt, &kq, 512) < 0.35);
+}
+
+#[test]
+fn kmer_hashing() {
+    use kira_cdh_compat_lsh::util::{canonical_kmer_hash, hash_kmer};
+    assert_eq!(hash_kmer(b"ACGTACGTA", 1), hash_kmer(b"ACGTACGTA", 1));
+    assert_ne!(hash_kmer(b"ACGTACGTA", 1), hash_kmer(b"ACGTACGTA", 2));
+    assert_ne!(hash_kmer(b"ACGT", 1), hash_kmer(b"ACGT\0", 1));
+    assert_eq!(
+        canonical_kmer_hash(b"AACG", b"CGTT", 7),
+        canonical_kmer_hash(b"CGTT", b"AACG", 7)
+    );
+}
+
+#[test]
+fn rolling_kmer_hasher() {
+    use kira_cdh_compat_lsh::util::RollingKmerHasher;
+    let seq = b"ACGTTGCAACGTAC";
+    let k = 4;
+    let mut roller = RollingKmerHasher::new(k, 9);
+    let rolled: Vec<Option<u64>> = seq.iter().map(|&b| roller.roll(b)).collect();
+    assert!(rolled[..k - 1].iter().all(Option::is_none));
+
+    // Each window's hash equals that of a fresh hasher fed only that window.
+    for (i, h) in rolled.iter().enumerate().skip(k - 1) {
+        let mut fresh = RollingKmerHasher::new(k, 9);
+        let direct = seq[i + 1 - k..=i].iter().map(|&b| fresh.roll(b)).last();
+        assert_eq!(*h, direct.unwrap());
+    }
+    // "ACGT" occurs twice.
+    assert_eq!(rolled[3], rolled[11]);
+
+    roller.reset();
+    assert!(roller.roll(b'A').is_none());
+}
+
+#[test]
+fn lsh_query_buffer_reuse() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sigs: Vec<Vec<u64>> = (0..20u64)
+        .map(|i| (0..16).map(|j| (i % 3) * 50 + j % 5).collect())
+        .collect();
+    for (i, sig) in sigs.iter().enumerate() {
+        idx.insert(i as u32, sig).unwrap();
+    }
+    let mut buf = LshQueryBuffer::new();
+    for _ in 0..2 {
+        for sig in &sigs {
+            let expected = idx.query_candidates(sig, 3);
+            assert_eq!(
+                idx.query_candidates_buf(sig, 3, &mut buf),
+                expected.as_slice()
+            );
+        }
+    }
+    // A query with no hits leaves nothing from the previous one behind.
+    assert!(idx.query_candidates_buf(&[999; 16], 1, &mut buf).is_empty());
+}
+
+#[test]
+fn hash_band_x4_matches_scalar() {
+    use kira_cdh_compat_lsh::util::{hash_band, hash_band_x4, splitmix64};
+    let data: Vec<u64> = (0..64).map(splitmix64).collect();
+    for rows in [0, 1, 3, 4, 7, 16] {
+        let chunks = [
+            &data[0..rows],
+            &data[16..16 + rows],
+            &data[32..32 + rows],
+            &data[48..48 + rows],
+        ];
+        let seeds = [1, 2, u64::MAX, 0xDEADBEEF];
+        let expected: Vec<u64> = (0..4).map(|i| hash_band(chunks[i], seeds[i])).collect();
+        assert_eq!(hash_band_x4(chunks, seeds).to_vec(), expected);
+    }
+}
+
+#[test]
+fn minhash_signature_snapshot() {
+    let mut mh = MinHash::new(32, 5);
+    mh.update_all(&[1, 2, 3]);
+    let early = mh.signature();
+    mh.update_all(&[4, 5, 6]);
+    assert!(early.iter().zip(mh.signature()).all(|(&e, l)| l <= e));
+    let snapshot = mh.signature();
+    assert_eq!(mh.finish(), snapshot);
+}
+
+#[test]
+fn reset_matches_fresh_sketch() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let first: Vec<u64> = (0..500).map(splitmix64).collect();
+    let second: Vec<u64> = (1000..1300).map(splitmix64).collect();
+
+    let mut mh = MinHash::new(64, 3);
+    mh.update_all(&first);
+    mh.reset();
+    mh.update_all(&second);
+    let mut fresh = MinHash::new(64, 3);
+    fresh.update_all(&second);
+    assert_eq!(mh.finish(), fresh.finish());
+
+    let mut kmv = KmvSketch::new(64);
+    first.iter().for_each(|&h| kmv.update(h));
+    kmv.reset();
+    assert!(kmv.is_empty());
+    second.iter().for_each(|&h| kmv.update(h));
+    let mut fresh = KmvSketch::new(64);
+    second.iter().for_each(|&h| fresh.update(h));
+    assert_eq!(kmv.finish(), fresh.finish());
+}
+
+#[test]
+fn sketch_num_updates() {
+    let mut mh = MinHash::new(16, 1);
+    mh.update(7);
+    mh.update(7);
+    mh.update_all(&[1, 2, 3]);
+    assert_eq!(mh.num_updates(), 5);
+    let mut other = MinHash::new(16, 1);
+    other.update(9);
+    mh.merge(&other).unwrap();
+    assert_eq!(mh.num_updates(), 6);
+    mh.reset();
+    assert_eq!(mh.num_updates(), 0);
+
+    let mut kmv = KmvSketch::new(4);
+    for h in [5, 5, 5, 1, 2, 3, 4, 0] {
+        kmv.update(h);
+    }
+    // Duplicates and evictions all count; `len` stays at k.
+    assert_eq!(kmv.num_updates(), 8);
+    assert_eq!(kmv.len(), 4);
+    kmv.reset();
+    assert_eq!(kmv.num_updates(), 0);
+}
+
+#[test]
+fn adaptive_kmv_matches_fixed_at_cap() {
+    use kira_cdh_compat_lsh::{kmv::AdaptiveKmvSketch, util::splitmix64};
+    for n in [3u64, 20, 100, 5000] {
+        let mut adaptive = AdaptiveKmvSketch::new(4, 256);
+        let mut fixed = KmvSketch::new(256);
+        for h in (0..n).chain(0..n).map(splitmix64) {
+            adaptive.update(h);
+            fixed.update(h);
+        }
+        assert_eq!(adaptive.len(), (n as usize).min(256));
+        assert!(adaptive.k() >= adaptive.len() && adaptive.k() <= 256);
+        assert_eq!(adaptive.finish(), fixed.finish());
+    }
+}
+
+#[test]
+fn kmv_union_and_intersection_cardinality() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let k = 1024;
+    let sketch = |range: std::ops::Range<u64>| {
+        let mut s = KmvSketch::new(k);
+        range.map(splitmix64).for_each(|h| s.update(h));
+        s.finish()
+    };
+    // |A| = |B| = 20000, |A ∪ B| = 30000, |A ∩ B| = 10000.
+    let (a, b) = (sketch(0..20_000), sketch(10_000..30_000));
+    let union = kmv_union_cardinality(&a, &b, k);
+    let inter = kmv_intersection_cardinality(&a, &b, k);
+    assert!((union - 30_000.0).abs() < 3_000.0, "union {union}");
+    assert!((inter - 10_000.0).abs() < 1_500.0, "intersection {inter}");
+
+    // Under-filled sketches are counted exactly.
+    let (small_a, small_b) = (sketch(0..10), sketch(5..20));
+    assert_eq!(kmv_union_cardinality(&small_a, &small_b, k), 20.0);
+    assert_eq!(kmv_intersection_cardinality(&small_a, &small_b, k), 5.0);
+    assert_eq!(kmv_intersection_cardinality(&[], &[], k), 0.0);
+}
+
+#[test]
+fn lsh_candidates_iter() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sigs: Vec<Vec<u64>> = (0..12u64)
+        .map(|i| (0..16).map(|j| (i % 4) * 10 + j % 3).collect())
+        .collect();
+    for (i, sig) in sigs.iter().enumerate() {
+        idx.insert(i as u32, sig).unwrap();
+    }
+    let mut lazy: Vec<(u32, u32)> = idx.candidates_iter(&sigs[0], 2).collect();
+    lazy.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    assert_eq!(lazy, idx.query_candidates(&sigs[0], 2));
+    assert_eq!(idx.candidates_iter(&sigs[0], 2).take(1).count(), 1);
+}
+
+#[test]
+fn lsh_query_candidates_checked() {
+    use kira_cdh_compat_lsh::errors::LshError;
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sig: Vec<u64> = (0..8).collect();
+    idx.insert(1, &sig).unwrap();
+    assert!(matches!(
+        idx.query_candidates_checked(&sig[..5], 1),
+        Err(LshError::ShortSignature {
+            sig_len: 5,
+            need: 8
+        })
+    ));
+    // Values past bands * rows are ignored.
+    let longer: Vec<u64> = (0..12).collect();
+    assert_eq!(
+        idx.query_candidates_checked(&longer, 4).unwrap(),
+        vec![(1, 4)]
+    );
+}
+
+#[test]
+fn lsh_query_candidates_weighted() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let query: Vec<u64> = (0..8).collect();
+    // id 1 shares bands 0 and 1; id 2 shares band 3 only.
+    idx.insert(1, &[0, 1, 2, 3, 90, 91, 92, 93]).unwrap();
+    idx.insert(2, &[80, 81, 82, 83, 84, 85, 6, 7]).unwrap();
+
+    let uniform = idx.query_candidates_weighted(&query, &[1.0; 4], 0.0);
+    assert_eq!(uniform, vec![(1, 2.0), (2, 1.0)]);
+    let weighted = idx.query_candidates_weighted(&query, &[0.25, 0.25, 1.0, 3.0], 1.0);
+    assert_eq!(weighted, vec![(2, 3.0)]);
+}
+
+#[test]
+#[should_panic(expected = "one weight per band")]
+fn lsh_query_candidates_weighted_rejects_wrong_len() {
+    let idx: LshIndex = LshIndex::with_params(LshParams::new(4, 2).unwrap());
+    idx.query_candidates_weighted(&[0; 8], &[1.0; 3], 0.0);
+}
+
+#[test]
+fn lsh_ensemble_aggregates_tables() {
+    use kira_cdh_compat_lsh::LshEnsemble;
+    let params = LshParams::new(8, 4).unwrap();
+    let base: Vec<u64> = (0..32).map(|i| i * 7 + 1).collect();
+    // Differs from `base` at one row in every band: a single table misses it.
+    let near: Vec<u64> = base
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| if i % 4 == 0 { v + 1000 } else { v })
+        .collect();
+
+    let mut single = LshIndex::with_params(params.clone());
+    single.insert(1, &near).unwrap();
+    assert!(single.query_candidates(&base, 1).is_empty());
+
+    let mut ens = LshEnsemble::new(6, params, 42);
+    assert_eq!(ens.num_tables(), 6);
+    ens.insert(1, &near).unwrap();
+    ens.insert(2, &base).unwrap();
+    ens.build();
+    let cands = ens.query_candidates(&base, 1);
+    // Exact match collides in every band of every table.
+    assert_eq!(cands[0], (2, 48));
+    assert!(cands.iter().any(|&(id, _)| id == 1));
+    assert!(ens.insert(3, &base[..10]).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn lsh_params_error_profile() {
+    let p = LshParams::new(20, 5).unwrap();
+    let profile = p.error_profile(&[0.0, 0.5, 0.9, 1.0]);
+    assert_eq!(profile[0], (0.0, 0.0));
+    assert_eq!(profile[3], (1.0, 1.0));
+    let expected = 1.0 - (1.0 - 0.5f64.powi(5)).powi(20);
+    assert!((profile[1].1 - expected).abs() < 1e-12);
+    assert!(profile.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert!(p.recall_at(0.9) > 0.99);
+    assert!(p.false_positive_at(0.3) < 0.05);
+}
+
+#[test]
+fn kmv_pad_signature() {
+    use kira_cdh_compat_lsh::kmv::{PAD_VALUE, pad_signature};
+    let mut sig = vec![3, 9, 27];
+    pad_signature(&mut sig, 8);
+    assert_eq!(
+        sig,
+        vec![
+            3, 9, 27, PAD_VALUE, PAD_VALUE, PAD_VALUE, PAD_VALUE, PAD_VALUE
+        ]
+    );
+    pad_signature(&mut sig, 4);
+    assert_eq!(sig.len(), 8);
+
+    let mut idx = LshIndex::with_params(LshParams::new(4, 2).unwrap());
+    idx.insert(0, &sig).unwrap();
+    assert_eq!(idx.query_candidates(&sig, 4), vec![(0, 4)]);
+}
+
+#[test]
+fn hash_band_bytes_is_endian_portable() {
+    use kira_cdh_compat_lsh::util::{hash_band, hash_band_bytes};
+    let sig: Vec<u64> = (0..4)
+        .map(|i| 0x0102030405060708u64.wrapping_mul(i + 1))
+        .collect();
+    let le: Vec<u8> = sig.iter().flat_map(|v| v.to_le_bytes()).collect();
+    assert_eq!(hash_band_bytes(&le, 3), hash_band(&sig, 3));
+
+    // Simulated big-endian host: native memory holds `to_be_bytes`; decoding
+    // natively and encoding with `to_le_bytes` yields the same stream.
+    let be_memory: Vec<[u8; 8]> = sig.iter().map(|v| v.to_be_bytes()).collect();
+    let from_be_host: Vec<u8> = be_memory
+        .iter()
+        .flat_map(|m| u64::from_be_bytes(*m).to_le_bytes())
+        .collect();
+    assert_eq!(hash_band_bytes(&from_be_host, 3), hash_band(&sig, 3));
+    // Reading native bytes without the conversion would give a different key.
+    let raw_be: Vec<u8> = sig.iter().flat_map(|v| v.to_be_bytes()).collect();
+    assert_ne!(hash_band_bytes(&raw_be, 3), hash_band(&sig, 3));
+}
+
+#[test]
+fn lsh_band_seeds() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let params = LshParams::new(4, 3).unwrap();
+    let idx: LshIndex = LshIndex::with_params_and_seed(params.clone(), 11);
+    let seeds = idx.band_seeds();
+    assert_eq!(seeds.len(), 4);
+    assert_eq!(seeds[2], splitmix64(11 ^ splitmix64(2)));
+    assert_eq!(
+        seeds,
+        LshIndex::<u32>::with_params_and_seed(params.clone(), 11).band_seeds()
+    );
+    assert_ne!(
+        seeds,
+        LshIndex::<u32>::with_params_and_seed(params, 12).band_seeds()
+    );
+}
+
+#[test]
+fn lsh_merge_shards() {
+    let params = LshParams::new(4, 2).unwrap();
+    let sigs: Vec<Vec<u64>> = (0..10u64)
+        .map(|i| (0..8).map(|j| (i % 3) * 20 + j).collect())
+        .collect();
+    let mut whole = LshIndex::with_params(params.clone());
+    let mut left = LshIndex::with_params(params.clone());
+    let mut right = LshIndex::with_params(params.clone());
+    for (i, sig) in sigs.iter().enumerate() {
+        whole.insert(i as u32, sig).unwrap();
+        let shard = if i < 5 { &mut left } else { &mut right };
+        shard.insert(i as u32, sig).unwrap();
+    }
+    left.merge(right).unwrap();
+    assert_eq!(left.len(), whole.len());
+    assert!(left.contains(9));
+    for sig in &sigs {
+        assert_eq!(
+            left.query_candidates(sig, 1),
+            whole.query_candidates(sig, 1)
+        );
+    }
+
+    let other_seed = LshIndex::with_params_and_seed(params.clone(), 1);
+    assert!(matches!(
+        left.merge(other_seed),
+        Err(kira_cdh_compat_lsh::errors::LshError::IncompatibleIndex)
+    ));
+    assert!(
+        left.merge(LshIndex::with_params(LshParams::new(2, 4).unwrap()))
+            .is_err()
+    );
+}
+
+#[test]
+fn kmv_finish_many_keeps_order() {
+    use kira_cdh_compat_lsh::{kmv::finish_many, util::splitmix64};
+    let sketches: Vec<KmvSketch> = (0..20u64)
+        .map(|i| {
+            let mut s = KmvSketch::new(16);
+            (i * 100..i * 100 + 10 + i)
+                .map(splitmix64)
+                .for_each(|h| s.update(h));
+            s
+        })
+        .collect();
+    let expected: Vec<Vec<u64>> = sketches.iter().cloned().map(KmvSketch::finish).collect();
+    #[cfg(feature = "parallel")]
+    assert_eq!(
+        kira_cdh_compat_lsh::kmv::finish_many_parallel(sketches.clone()),
+        expected
+    );
+    assert_eq!(finish_many(sketches), expected);
+}
+
+#[cfg(feature = "sip")]
+#[test]
+fn lsh_sip_band_hasher() {
+    use kira_cdh_compat_lsh::BandHasher;
+    let params = LshParams::new(6, 2).unwrap();
+    let sigs: Vec<Vec<u64>> = (0..15u64)
+        .map(|i| (0..12).map(|j| (i % 4) * 30 + j % 5).collect())
+        .collect();
+    let mut fast: LshIndex = LshIndex::with_params_and_seed(params.clone(), 99);
+    let mut keyed = LshIndex::with_band_hasher(params.clone(), 99, BandHasher::Sip);
+    assert_eq!(keyed.band_hasher(), BandHasher::Sip);
+    for (i, sig) in sigs.iter().enumerate() {
+        fast.insert(i as u32, sig).unwrap();
+        keyed.insert(i as u32, sig).unwrap();
+    }
+    // Same collisions (barring 64-bit key clashes), different bucket keys.
+    for sig in &sigs {
+        assert_eq!(
+            keyed.query_candidates(sig, 1),
+            fast.query_candidates(sig, 1)
+        );
+    }
+    keyed.remove(0, &sigs[0]).unwrap();
+    assert!(!keyed.contains(0));
+    assert!(fast.merge(keyed).is_err());
+    // Keys are fixed by the seed, so they are stable across releases.
+    let pinned: LshIndex =
+        LshIndex::with_band_hasher(LshParams::new(2, 2).unwrap(), 99, BandHasher::Sip);
+    assert_eq!(
+        pinned.band_keys(&[1, 2, 3, 4]),
+        vec![0xA47FA1B7029E1BF6, 0x32ADB0357C8A16BC]
+    );
+
+    #[cfg(feature = "serde")]
+    {
+        let mut keyed = LshIndex::with_band_hasher(params, 99, BandHasher::Sip);
+        keyed.insert(1, &sigs[1]).unwrap();
+        let json = serde_json::to_string(&keyed).unwrap();
+        let back: LshIndex = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.band_hasher(), BandHasher::Sip);
+        assert_eq!(back.query_candidates(&sigs[1], 6), vec![(1, 6)]);
+    }
+}
+
+#[test]
+fn jaccard_kmv_robust_uses_shorter_length() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let sketch = |range: std::ops::Range<u64>| {
+        let mut s = KmvSketch::new(256);
+        range.map(splitmix64).for_each(|h| s.update(h));
+        s.finish()
+    };
+    // Full sketch of a large set against an under-filled one.
+    let (big, small) = (sketch(0..10_000), sketch(0..100));
+    assert_eq!(small.len(), 100);
+    assert_eq!(
+        jaccard_kmv_robust(&big, &small),
+        jaccard_kmv(&big, &small, 100)
+    );
+    assert!(jaccard_kmv_robust(&big, &small) < 0.1);
+
+    let (a, b) = (sketch(0..60), sketch(20..80));
+    assert_eq!(jaccard_kmv(&a, &b, a.len() + b.len()), 0.5);
+    assert_eq!(jaccard_kmv_robust(&a, &a), 1.0);
+    assert_eq!(jaccard_kmv_robust(&a, &[]), 0.0);
+}
+
+#[test]
+fn lsh_query_tiered() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let query: Vec<u64> = (0..16).collect();
+    for shared_bands in 0..=8u64 {
+        // Signature sharing the first `shared_bands` bands with the query.
+        let sig: Vec<u64> = (0..16)
+            .map(|j| if j / 2 < shared_bands { j } else { 1000 + j })
+            .collect();
+        idx.insert(shared_bands as u32, &sig).unwrap();
+    }
+    let tiers = idx.query_tiered(&query, &[1, 4, 8]);
+    assert_eq!(tiers.len(), 3);
+    for (tier, &t) in tiers.iter().zip(&[1, 4, 8]) {
+        assert_eq!(*tier, idx.query_candidates(&query, t));
+    }
+    assert_eq!(tiers[2], vec![(8, 8)]);
+    assert_eq!(idx.query_tiered(&query, &[6, 2])[0].len(), 3);
+    assert!(idx.query_tiered(&query, &[]).is_empty());
+}
+
+#[test]
+fn lsh_compile_matches_source_index() {
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sigs: Vec<Vec<u64>> = (0..40u64)
+        .map(|i| {
+            (0..16)
+                .map(|j| (i % 6) * 40 + (j * (i % 3 + 1)) % 7)
+                .collect()
+        })
+        .collect();
+    for (i, sig) in sigs.iter().enumerate() {
+        idx.insert(i as u32, sig).unwrap();
+    }
+    idx.set_max_bucket_size(Some(6));
+    let expected: Vec<_> = sigs.iter().map(|s| idx.query_candidates(s, 1)).collect();
+    let compact = idx.compile();
+    assert_eq!(compact.len(), 40);
+    for (sig, want) in sigs.iter().zip(&expected) {
+        assert_eq!(&compact.query_candidates(sig, 1), want);
+    }
+    assert!(compact.query_candidates(&[12345; 16], 1).is_empty());
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn minhash_update_all_parallel_matches_serial() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let xs: Vec<u64> = (0..2000).map(splitmix64).collect();
+    let mut serial = MinHash::new(512, 8);
+    let mut parallel = MinHash::new(512, 8);
+    serial.update_all(&xs);
+    parallel.update_all_parallel(&xs);
+    assert_eq!(parallel.num_updates(), 2000);
+    assert_eq!(parallel.finish(), serial.finish());
+}
+
+#[test]
+fn lsh_tie_break_orders() {
+    use kira_cdh_compat_lsh::TieBreak;
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sig: Vec<u64> = (0..8).collect();
+    for id in [7u32, 3, 9, 1] {
+        idx.insert(id, &sig).unwrap();
+    }
+    let ids = |idx: &LshIndex| -> Vec<u32> {
+        idx.query_candidates(&sig, 1)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    };
+    assert_eq!(idx.tie_break(), TieBreak::LowerId);
+    assert_eq!(ids(&idx), vec![1, 3, 7, 9]);
+    idx.set_tie_break(TieBreak::HigherId);
+    assert_eq!(ids(&idx), vec![9, 7, 3, 1]);
+    idx.set_tie_break(TieBreak::InsertionOrder);
+    assert_eq!(ids(&idx), vec![7, 3, 9, 1]);
+
+    // Fully removing and re-inserting an ID moves it to the back.
+    idx.remove(3, &sig).unwrap();
+    idx.insert(3, &sig).unwrap();
+    assert_eq!(ids(&idx), vec![7, 9, 1, 3]);
+
+    #[cfg(feature = "serde")]
+    {
+        let back: LshIndex = serde_json::from_str(&serde_json::to_string(&idx).unwrap()).unwrap();
+        assert_eq!(back.tie_break(), TieBreak::InsertionOrder);
+        assert_eq!(ids(&back), vec![7, 9, 1, 3]);
+    }
+}
+
+#[test]
+fn lsh_tie_break_applies_to_every_ranked_query() {
+    use kira_cdh_compat_lsh::{LshEnsemble, TieBreak};
+    let params = LshParams::new(4, 2).unwrap();
+    let sig: Vec<u64> = (0..8).collect();
+    let ids = |hits: Vec<(u32, u32)>| -> Vec<u32> { hits.into_iter().map(|(id, _)| id).collect() };
+    for tie_break in [TieBreak::HigherId, TieBreak::InsertionOrder] {
+        let mut idx = LshIndex::with_params_storing(params.clone());
+        idx.set_tie_break(tie_break);
+        for id in [7u32, 3, 9, 1] {
+            idx.insert(id, &sig).unwrap();
+        }
+        let expected = ids(idx.query_candidates(&sig, 1));
+        assert_ne!(expected, vec![1, 3, 7, 9]);
+        assert_eq!(ids(idx.query_top_k(&sig, 4)), expected);
+        assert_eq!(ids(idx.query_top_k(&sig, 2)), expected[..2]);
+        assert_eq!(ids(idx.query_candidates_verified(&sig, 1)), expected);
+        assert_eq!(ids(idx.query_candidates_bands(&sig, 1..3, 1)), expected);
+        assert_eq!(ids(idx.query_candidates_multiprobe(&sig, 1, 2)), expected);
+        let detailed: Vec<u32> = idx
+            .query_candidates_detailed(&sig, 1)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(detailed, expected);
+        let weighted: Vec<u32> = idx
+            .query_candidates_weighted(&sig, &[1.0; 4], 0.5)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(weighted, expected);
+
+        // Compiling keeps the order, also for IDs inserted afterwards.
+        let mut compact = idx.compile();
+        assert_eq!(compact.tie_break(), tie_break);
+        assert_eq!(ids(compact.query_candidates(&sig, 1)), expected);
+        compact.insert(5, &sig).unwrap();
+        let mut grown = expected.clone();
+        match tie_break {
+            TieBreak::HigherId => grown.insert(2, 5),
+            _ => grown.push(5),
+        }
+        assert_eq!(ids(compact.query_candidates(&sig, 1)), grown);
+    }
+
+    let mut ensemble: LshEnsemble = LshEnsemble::new(2, params, 1);
+    ensemble.set_tie_break(TieBreak::HigherId);
+    for id in [7u32, 3, 9, 1] {
+        ensemble.insert(id, &sig).unwrap();
+    }
+    assert_eq!(ensemble.tie_break(), TieBreak::HigherId);
+    assert_eq!(ids(ensemble.query_candidates(&sig, 1)), vec![9, 7, 3, 1]);
+}
+
+#[test]
+fn lsh_band_keys_explain_collisions() {
+    use kira_cdh_compat_lsh::util::hash_band;
+    let params = LshParams::new(5, 3).unwrap();
+    let idx: LshIndex = LshIndex::with_params_and_seed(params, 4);
+    let a: Vec<u64> = (0..15).collect();
+    let mut b = a.clone();
+    b[7] = 99; // band 2 differs
+    let (ka, kb) = (idx.band_keys(&a), idx.band_keys(&b));
+    assert_eq!(ka.len(), 5);
+    let differing: Vec<usize> = (0..5).filter(|&i| ka[i] != kb[i]).collect();
+    assert_eq!(differing, vec![2]);
+    assert_eq!(ka[1], hash_band(&a[3..6], idx.band_seeds()[1]));
+}
+
+#[test]
+fn frac_minhash_estimates() {
+    use kira_cdh_compat_lsh::{
+        sketch::{FracMinHash, containment_frac, jaccard_frac},
+        util::splitmix64,
+    };
+    let sketch = |range: std::ops::Range<u64>| {
+        let mut s = FracMinHash::new(0.05);
+        // Every value twice: repeats are ignored.
+        range
+            .flat_map(|x| [x, x])
+            .map(splitmix64)
+            .for_each(|h| s.update(h));
+        s.finish()
+    };
+    // Small genome fully contained in a 20x larger set.
+    let small = sketch(0..5_000);
+    let big = sketch(0..100_000);
+    assert!(small.windows(2).all(|w| w[0] < w[1]));
+    assert!((small.len() as f64 - 250.0).abs() < 60.0);
+    assert_eq!(containment_frac(&small, &big), 1.0);
+    assert!((jaccard_frac(&small, &big) - 0.05).abs() < 0.01);
+    assert!(containment_frac(&big, &small) < 0.1);
+
+    let (a, b) = (sketch(0..40_000), sketch(20_000..60_000));
+    assert!((jaccard_frac(&a, &b) - 1.0 / 3.0).abs() < 0.05);
+    assert_eq!(jaccard_frac(&[], &[]), 0.0);
+    assert_eq!(containment_frac(&[], &a), 0.0);
+}
+
+#[test]
+fn lsh_iter_band() {
+    let params = LshParams::new(3, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let a: Vec<u64> = (0..6).collect();
+    let b: Vec<u64> = vec![0, 1, 50, 51, 52, 53];
+    idx.insert(1, &a).unwrap();
+    idx.insert(2, &b).unwrap();
+    assert_eq!(idx.num_bands(), 3);
+    let mut band0: Vec<(u64, Vec<u32>)> =
+        idx.iter_band(0).map(|(k, ids)| (k, ids.to_vec())).collect();
+    assert_eq!(band0.len(), 1);
+    assert_eq!(band0.pop().unwrap(), (idx.band_keys(&a)[0], vec![1, 2]));
+    assert_eq!(idx.iter_band(2).count(), 2);
+    let total: usize = idx.iter_band(1).map(|(_, ids)| ids.len()).sum();
+    assert_eq!(total, 2);
+}
+
+#[test]
+fn dice_and_overlap_from_minhash() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let sketch = |range: std::ops::Range<u64>| {
+        let mut mh = MinHash::new(512, 21);
+        let xs: Vec<u64> = range.map(splitmix64).collect();
+        mh.update_all(&xs);
+        mh.finish()
+    };
+    // |A| = 1000 inside |B| = 3000: J = 1/3, Dice = 1/2, overlap = 1.
+    let (a, b) = (sketch(0..1000), sketch(0..3000));
+    let j = jaccard_from_signatures(&a, &b);
+    assert_eq!(dice_from_signatures(&a, &b), 2.0 * j / (1.0 + j));
+    assert!((dice_from_signatures(&a, &b) - 0.5).abs() < 0.06);
+    assert!(overlap_coefficient(&a, &b) > 0.8);
+    assert_eq!(dice_from_signatures(&a, &a), 1.0);
+    assert_eq!(overlap_coefficient(&a, &sketch(5000..6000)), 0.0);
+}
+
+#[test]
+fn lsh_insert_kmers() {
+    use kira_cdh_compat_lsh::{
+        errors::LshError,
+        kmv::{PAD_VALUE, pad_signature},
+        util::splitmix64,
+    };
+    let params = LshParams::new(8, 4).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let long: Vec<u64> = (0..500).map(splitmix64).collect();
+    idx.insert_kmers(1, long.iter().copied(), 32).unwrap();
+    idx.insert_kmers(2, (0..10).map(splitmix64), 32).unwrap();
+    assert!(matches!(
+        idx.insert_kmers(3, long.iter().copied(), 16),
+        Err(LshError::InvalidParams { sig_len: 16, .. })
+    ));
+
+    let mut kmv = KmvSketch::new(32);
+    long.iter().for_each(|&h| kmv.update(h));
+    assert_eq!(idx.query_candidates(&kmv.finish(), 8), vec![(1, 8)]);
+    let mut short: Vec<u64> = KmvSketch::from_signature(32, &{
+        let mut v: Vec<u64> = (0..10).map(splitmix64).collect();
+        v.sort_unstable();
+        v
+    })
+    .unwrap()
+    .finish();
+    pad_signature(&mut short, 32);
+    assert_eq!(short[31], PAD_VALUE);
+    assert_eq!(idx.query_candidates(&short, 8), vec![(2, 8)]);
+}
+
+#[test]
+fn lsh_query_candidates_detailed_lists_bands() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let sig: Vec<u64> = (0..8).collect();
+    let mut half = sig.clone();
+    half[4] = 100;
+    half[7] = 101;
+    idx.insert(1, &sig).unwrap();
+    idx.insert(2, &half).unwrap();
+
+    let detailed = idx.query_candidates_detailed(&sig, 1);
+    assert_eq!(detailed, vec![(1, vec![0, 1, 2, 3]), (2, vec![0, 1])]);
+    let counts: Vec<(u32, u32)> = detailed
+        .iter()
+        .map(|(id, bands)| (*id, bands.len() as u32))
+        .collect();
+    assert_eq!(counts, idx.query_candidates(&sig, 1));
+    assert_eq!(idx.query_candidates_detailed(&sig, 3).len(), 1);
+}
+
+#[test]
+fn lsh_verified_query_beyond_u16_bands() {
+    // More bands than a u16 band index can name.
+    let bands = 70_000;
+    let params = LshParams::new(bands, 1).unwrap();
+    let mut idx = LshIndex::with_params_storing(params);
+    let a: Vec<u64> = (0..bands as u64).collect();
+    let mut b = a.clone();
+    b[bands - 1] = u64::MAX;
+    idx.insert(1, &a).unwrap();
+    idx.insert(2, &b).unwrap();
+
+    let detailed = idx.query_candidates_detailed(&a, 1);
+    assert_eq!(detailed[0].1.last(), Some(&(bands as u32 - 1)));
+    assert_eq!(detailed[1].1.len(), bands - 1);
+    let n = bands as u32;
+    assert_eq!(
+        idx.query_candidates_verified(&a, 1),
+        vec![(1, n), (2, n - 1)]
+    );
+}
+
+#[test]
+fn lsh_params_estimate_bytes_tracks_built_index() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let params = LshParams::new(8, 2).unwrap();
+    assert_eq!(params.estimate_bytes(0, 1.0), 0);
+    assert!(params.estimate_bytes(2000, 1.0) > params.estimate_bytes(1000, 1.0));
+    assert!(params.estimate_bytes(1000, 10.0) < params.estimate_bytes(1000, 1.0));
+    assert_eq!(
+        params.estimate_bytes(1000, 0.1),
+        params.estimate_bytes(1000, 1.0)
+    );
+
+    let mut idx = LshIndex::with_params(params.clone());
+    for id in 0..1000u32 {
+        let sig: Vec<u64> = (0..16)
+            .map(|i| splitmix64(u64::from(id) * 16 + i))
+            .collect();
+        idx.insert(id, &sig).unwrap();
+    }
+    let actual = idx.stats().estimated_bytes as f64;
+    let estimate = params.estimate_bytes(1000, 1.0) as f64;
+    assert!(estimate > actual / 3.0 && estimate < actual * 3.0);
+}
+
+#[test]
+fn lsh_duplicate_inserts_count_each_band_once() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..8).collect();
+    idx.insert(7u32, &s).unwrap();
+    idx.insert(7, &s).unwrap();
+    idx.insert(7, &s).unwrap();
+
+    assert_eq!(idx.query_candidates(&s, 1), vec![(7, 4)]);
+    assert_eq!(idx.query_top_k(&s, 1), vec![(7, 4)]);
+    assert_eq!(idx.candidates_iter(&s, 1).collect::<Vec<_>>(), vec![(7, 4)]);
+    assert_eq!(
+        idx.query_candidates_weighted(&s, &[1.0; 4], 0.0),
+        vec![(7, 4.0)]
+    );
+    assert_eq!(idx.compile().query_candidates(&s, 1), vec![(7, 4)]);
+}
+
+#[test]
+fn lsh_build_dedups_reinserted_ids() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let s: Vec<u64> = (0..8).collect();
+    idx.insert(3u32, &s).unwrap();
+    idx.insert(3, &s).unwrap();
+    idx.insert(1, &s).unwrap();
+    assert_eq!(idx.len(), 3);
+
+    idx.build();
+    assert_eq!(idx.len(), 2);
+    for b in 0..idx.num_bands() {
+        let buckets: Vec<_> = idx.iter_band(b).collect();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].1, &[1, 3]);
+    }
+    assert_eq!(idx.query_candidates(&s, 1), vec![(1, 4), (3, 4)]);
+
+    idx.remove(3, &s).unwrap();
+    assert_eq!(idx.len(), 1);
+    assert!(!idx.contains(3));
+}
+
+#[test]
+fn kmv_current_max_and_quantile() {
+    let mut kmv = KmvSketch::new(5);
+    assert_eq!(kmv.current_max(), None);
+    assert_eq!(kmv.quantile(0.5), None);
+    for h in [50u64, 10, 40, 20, 30, 90, 5] {
+        kmv.update(h);
+    }
+    // Kept: 5, 10, 20, 30, 40.
+    assert_eq!(kmv.current_max(), Some(40));
+    assert_eq!(kmv.quantile(0.0), Some(5));
+    assert_eq!(kmv.quantile(0.5), Some(20));
+    assert_eq!(kmv.quantile(1.0), Some(40));
+
+    let before = kmv.clone().finish();
+    kmv.update(kmv.current_max().unwrap());
+    kmv.update(41);
+    assert_eq!(kmv.finish(), before);
+}
+
+#[test]
+fn custom_mixer_supports_every_constructor_option() {
+    use kira_cdh_compat_lsh::{BandHasher, util::Mixer};
+
+    struct Xor;
+    impl Mixer for Xor {
+        fn mix(&self, x: u64, seed: u64) -> u64 {
+            kira_cdh_compat_lsh::util::splitmix64(x ^ seed)
+        }
+    }
+
+    let params = LshParams::new(4, 2).unwrap();
+    #[cfg(feature = "sip")]
+    let hasher = BandHasher::Sip;
+    #[cfg(not(feature = "sip"))]
+    let hasher = BandHasher::SplitMix;
+    let mut idx = LshIndex::with_mixer(params, 5, Xor)
+        .storing_signatures()
+        .using_band_hasher(hasher);
+    idx.reserve(16);
+    assert_eq!(idx.band_hasher(), hasher);
+    let a: Vec<u64> = (0..8).collect();
+    let mut b = a.clone();
+    b[0] = 100;
+    idx.insert(1u32, &a).unwrap();
+    idx.insert(2, &b).unwrap();
+    assert_eq!(idx.query_by_id(1, 1), Some(vec![(1, 4), (2, 3)]));
+    assert_eq!(idx.refine(&a, &[(2, 3)]), vec![(2, 7.0 / 8.0)]);
+}
+
+#[test]
+#[should_panic(expected = "band hasher must be chosen before inserting")]
+fn using_band_hasher_rejects_non_empty_index() {
+    use kira_cdh_compat_lsh::BandHasher;
+    let mut idx: LshIndex = LshIndex::with_params(LshParams::new(2, 1).unwrap());
+    idx.insert(0, &[1, 2]).unwrap();
+    let _ = idx.using_band_hasher(BandHasher::SplitMix);
+}
+
+#[test]
+fn custom_mixer_is_consistent_and_default_matches_builtin() {
+    use kira_cdh_compat_lsh::util::{Mixer, SplitMix64, splitmix64};
+
+    #[derive(Clone, Copy, Default)]
+    struct Murmurish;
+    impl Mixer for Murmurish {
+        fn mix(&self, x: u64, seed: u64) -> u64 {
+            let mut z = (x ^ seed).wrapping_mul(0xff51afd7ed558ccd);
+            z ^= z >> 33;
+            z.wrapping_mul(0xc4ceb9fe1a85ec53) ^ (z >> 29)
+        }
+    }
+
+    let xs: Vec<u64> = (0..200).map(splitmix64).collect();
+    let mut plain = MinHash::new(32, 9);
+    let mut explicit = MinHash::with_mixer(32, 9, SplitMix64);
+    let mut custom = MinHash::with_mixer(32, 9, Murmurish);
+    plain.update_all(&xs);
+    explicit.update_all(&xs);
+    xs.iter().for_each(|&x| custom.update(x));
+    let sig = plain.finish();
+    assert_eq!(explicit.finish(), sig);
+    let custom_sig = custom.finish();
+    assert_ne!(custom_sig, sig);
+
+    let params = LshParams::new(8, 4).unwrap();
+    let default_idx: LshIndex = LshIndex::with_params_and_seed(params.clone(), 5);
+    let builtin: LshIndex<u32, SplitMix64> = LshIndex::with_mixer(params.clone(), 5, SplitMix64);
+    assert_eq!(default_idx.band_keys(&sig), builtin.band_keys(&sig));
+
+    let mut idx = LshIndex::with_mixer(params, 5, Murmurish);
+    assert_ne!(idx.band_keys(&sig), builtin.band_keys(&sig));
+    idx.insert(1u32, &custom_sig).unwrap();
+    assert_eq!(idx.query_candidates(&custom_sig, 8), vec![(1, 8)]);
+    assert_eq!(idx.compile().query_candidates(&custom_sig, 8), vec![(1, 8)]);
+}
+
+#[test]
+fn jaccard_sorted_sets_is_exact_set_jaccard() {
+    assert_eq!(jaccard_sorted_sets(&[], &[]), 0.0);
+    assert_eq!(jaccard_sorted_sets(&[1, 2, 3], &[1, 2, 3]), 1.0);
+    assert_eq!(jaccard_sorted_sets(&[1, 3, 5, 7], &[3, 4, 5]), 2.0 / 5.0);
+    assert_eq!(jaccard_sorted_sets(&[1, 2], &[]), 0.0);
+
+    // One extra small value shifts every position of a KMV signature, but the
+    // sets still overlap almost entirely.
+    let a: Vec<u64> = (10..20).collect();
+    let b: Vec<u64> = core::iter::once(0).chain(10..19).collect();
+    assert_eq!(jaccard_from_signatures(&a, &b), 0.0);
+    assert_eq!(jaccard_sorted_sets(&a, &b), 9.0 / 11.0);
+}
+
+#[test]
+fn lsh_insert_if_novel_skips_near_duplicates() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let rep: Vec<u64> = (0..8).collect();
+    let mut near = rep.clone();
+    near[7] = 100; // collides in 3 bands
+    let far: Vec<u64> = (50..58).collect();
+
+    assert!(idx.insert_if_novel(1u32, &rep, 2).unwrap());
+    assert!(!idx.insert_if_novel(2, &near, 2).unwrap());
+    assert!(idx.insert_if_novel(3, &near, 3).unwrap());
+    assert!(idx.insert_if_novel(4, &far, 0).unwrap());
+    assert!(!idx.contains(2));
+    assert_eq!(idx.len(), 3);
+    assert!(idx.insert_if_novel(5, &rep[..4], 2).is_err());
+}
+
+#[test]
+fn lsh_greedy_cluster_in_input_order() {
+    use kira_cdh_compat_lsh::lsh::greedy_cluster;
+    let params = LshParams::new(8, 2).unwrap();
+    let a: Vec<u64> = (0..16).collect();
+    let mut a2 = a.clone();
+    a2[15] = 99; // J = 15/16 with `a`
+    let b: Vec<u64> = (100..116).collect();
+    let mut b2 = b.clone();
+    b2[0] = 7;
+    let mut a3 = a.clone();
+    a3[8..].copy_from_slice(&[200, 201, 202, 203, 204, 205, 206, 207]); // J = 1/2
+
+    let input = vec![(10u32, a), (11, b), (12, a2), (13, b2), (14, a3)];
+    let clusters = greedy_cluster(&input, params.clone(), 1, 0.9);
+    assert_eq!(clusters, vec![vec![10, 12], vec![11, 13], vec![14]]);
+    let loose = greedy_cluster(&input, params, 1, 0.5);
+    assert_eq!(loose, vec![vec![10, 12, 14], vec![11, 13]]);
+}
+
+#[test]
+fn lsh_query_candidates_unsorted_same_pairs() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let base: Vec<u64> = (0..16).map(splitmix64).collect();
+    for id in 0..20u32 {
+        let mut sig = base.clone();
+        for v in sig.iter_mut().skip(id as usize % 16) {
+            *v ^= u64::from(id);
+        }
+        idx.insert(id, &sig).unwrap();
+    }
+    let sorted = idx.query_candidates(&base, 2);
+    let mut unsorted = idx.query_candidates_unsorted(&base, 2);
+    assert!(!sorted.is_empty());
+    unsorted.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    assert_eq!(unsorted, sorted);
+}
+
+#[test]
+fn kmv_coverage_shrinks_with_more_elements() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    assert_eq!(kmv_coverage(&[]), 0.0);
+    assert_eq!(kmv_coverage(&[0, u64::MAX]), 1.0);
+
+    let coverage = |n: u64| {
+        let mut kmv = KmvSketch::new(64);
+        (0..n).for_each(|x| kmv.update(splitmix64(x)));
+        kmv_coverage(&kmv.finish())
+    };
+    let (small, large) = (coverage(100), coverage(100_000));
+    assert!(small > 0.3);
+    assert!(large < 0.01 && large > 0.0);
+}
+
+#[test]
+fn sketch_build_signatures_in_input_order() {
+    use kira_cdh_compat_lsh::sketch::{build_kmv_signatures, build_minhash_signatures};
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let inputs: Vec<(u32, Vec<u64>)> = (0..50u32)
+        .rev()
+        .map(|id| (id, (0..100 + u64::from(id)).map(splitmix64).collect()))
+        .collect();
+
+    let minhash = build_minhash_signatures(&inputs, 32, 7);
+    let kmv = build_kmv_signatures(&inputs, 16);
+    assert_eq!(minhash.len(), inputs.len());
+    for (((id, hashes), (mid, msig)), (kid, ksig)) in inputs.iter().zip(&minhash).zip(&kmv) {
+        assert_eq!((id, id), (mid, kid));
+        let mut mh = MinHash::new(32, 7);
+        hashes.iter().for_each(|&h| mh.update(h));
+        assert_eq!(msig, &mh.finish());
+        let mut sk = KmvSketch::new(16);
+        hashes.iter().for_each(|&h| sk.update(h));
+        assert_eq!(ksig, &sk.finish());
+    }
+}
+
+#[test]
+fn lsh_params_balanced_factorization() {
+    use kira_cdh_compat_lsh::lsh::LshParamsError;
+    let dims = |n| {
+        let p = LshParams::balanced(n).unwrap();
+        (p.bands, p.rows_per_band)
+    };
+    assert_eq!(dims(128), (16, 8));
+    assert_eq!(dims(100), (10, 10));
+    assert_eq!(dims(96), (12, 8));
+    assert_eq!(dims(1), (1, 1));
+    assert!(matches!(
+        LshParams::balanced(127),
+        Err(LshParamsError::Prime(127))
+    ));
+    assert!(matches!(LshParams::balanced(0), Err(LshParamsError::Zero)));
+}
+
+#[test]
+fn lsh_strict_rejects_wrong_length_inserts() {
+    use kira_cdh_compat_lsh::errors::LshError;
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let long: Vec<u64> = (0..10).collect();
+    idx.insert(0u32, &long).unwrap();
+
+    idx.set_strict(true);
+    assert!(idx.strict());
+    assert!(matches!(
+        idx.insert(1, &long),
+        Err(LshError::LengthMismatch {
+            sig_len: 10,
+            need: 8
+        })
+    ));
+    assert!(matches!(
+        idx.insert(1, &long[..6]),
+        Err(LshError::ShortSignature { .. })
+    ));
+    idx.insert(1, &long[..8]).unwrap();
+    // Queries still take the prefix of a longer signature.
+    assert_eq!(idx.query_candidates(&long, 4), vec![(0, 4), (1, 4)]);
+}
+
+#[test]
+fn unified_error_wraps_every_crate_error() {
+    use kira_cdh_compat_lsh::errors::{self, Error, LshError, SketchError};
+
+    fn pipeline(num_hashes: usize, k: usize) -> errors::Result<Vec<(u32, u32)>> {
+        let params = LshParams::balanced(num_hashes)?;
+        let mut kmv = KmvSketch::try_new(k)?;
+        (0..100u64).for_each(|h| kmv.update(h));
+        let mut idx = LshIndex::with_params(params);
+        let sig = kmv.finish();
+        idx.insert(1, &sig)?;
+        Ok(idx.query_candidates(&sig, 1))
+    }
+
+    assert_eq!(pipeline(16, 16).unwrap(), vec![(1, 4)]);
+    assert!(matches!(pipeline(13, 16), Err(Error::Params(_))));
+    let err = pipeline(16, 0).unwrap_err();
+    assert!(matches!(err, Error::Sketch(SketchError::ZeroK)));
+    assert_eq!(err.to_string(), SketchError::ZeroK.to_string());
+    assert!(matches!(
+        pipeline(64, 16),
+        Err(Error::Lsh(LshError::ShortSignature { .. }))
+    ));
+}
+
+#[test]
+fn lsh_verify_band_collision_filters_false_key_collisions() {
+    use kira_cdh_compat_lsh::util::Mixer;
+
+    // Folds every window to the same key, so all bands "collide".
+    struct Constant;
+    impl Mixer for Constant {
+        fn mix(&self, _x: u64, _seed: u64) -> u64 {
+            0
+        }
+    }
+
+    let params = LshParams::new(4, 2).unwrap();
+    let a: Vec<u64> = (0..8).collect();
+    let mut b = a.clone();
+    b[0] = 50;
+    b[7] = 51;
+    let plain: LshIndex = LshIndex::with_params(params.clone());
+    assert!(plain.verify_band_collision(&a, &b, 1));
+    assert!(!plain.verify_band_collision(&a, &b, 0));
+
+    let mut idx = LshIndex::with_mixer(params, 0, Constant);
+    idx.insert(1u32, &b).unwrap();
+    assert_eq!(idx.query_candidates(&a, 1), vec![(1, 4)]);
+    // Without stored signatures there is nothing to verify against.
+    assert_eq!(idx.query_candidates_verified(&a, 1), vec![(1, 4)]);
+
+    let mut storing = LshIndex::with_params_storing(LshParams::new(4, 2).unwrap());
+    storing.insert(1u32, &b).unwrap();
+    storing.insert(2, &a).unwrap();
+    assert_eq!(
+        storing.query_candidates_verified(&a, 1),
+        storing.query_candidates(&a, 1)
+    );
+}
+
+#[test]
+fn minhash32_signatures_index_and_compare() {
+    use kira_cdh_compat_lsh::minhash::MinHash32;
+    use kira_cdh_compat_lsh::sketch::jaccard_from_signatures_u32;
+    use kira_cdh_compat_lsh::util::splitmix64;
+
+    let a: Vec<u64> = (0..1000).map(splitmix64).collect();
+    let b: Vec<u64> = (200..1200).map(splitmix64).collect();
+    let sketch = |xs: &[u64]| {
+        let mut mh = MinHash32::new(64, 3);
+        mh.update_all(xs);
+        mh.finish()
+    };
+    let (sa, sb) = (sketch(&a), sketch(&b));
+
+    let mut full = MinHash::new(64, 3);
+    a.iter().for_each(|&x| full.update(x));
+    let high: Vec<u32> = full.finish().iter().map(|&v| (v >> 32) as u32).collect();
+    assert_eq!(sa, high);
+
+    let mut one = MinHash32::new(64, 3);
+    a.iter().for_each(|&x| one.update(x));
+    assert_eq!(one.finish(), sa);
+
+    let j = jaccard_from_signatures_u32(&sa, &sb);
+    assert!((j - 800.0 / 1200.0).abs() < 0.2, "j={j}");
+    assert_eq!(jaccard_from_signatures_u32(&sa, &sa), 1.0);
+
+    let mut idx = LshIndex::with_params(LshParams::new(16, 4).unwrap());
+    idx.insert_u32(1u32, &sa).unwrap();
+    idx.insert_u32(2, &sb).unwrap();
+    assert_eq!(idx.query_candidates_u32(&sa, 16), vec![(1, 16)]);
+}
+
+#[test]
+fn u32_rows_fold_like_widened_rows() {
+    use kira_cdh_compat_lsh::util::{Mixer, hash_band, hash_band_u32};
+
+    // Overrides `fold` only: the default `fold_u32` must still agree with it.
+    struct Summing;
+    impl Mixer for Summing {
+        fn mix(&self, x: u64, seed: u64) -> u64 {
+            x ^ seed
+        }
+        fn fold(&self, chunk: &[u64], seed: u64) -> u64 {
+            chunk
+                .iter()
+                .fold(seed, |acc, &v| acc.wrapping_mul(31).wrapping_add(v))
+        }
+    }
+
+    let narrow: Vec<u32> = (0..12u32).map(|i| i.wrapping_mul(0x9E37_79B9)).collect();
+    let wide: Vec<u64> = narrow.iter().map(|&v| u64::from(v)).collect();
+    assert_eq!(hash_band_u32(&narrow, 7), hash_band(&wide, 7));
+    assert_eq!(Summing.fold_u32(&narrow, 7), Summing.fold(&wide, 7));
+
+    let params = LshParams::new(6, 2).unwrap();
+    let mut idx = LshIndex::with_params_storing(params.clone());
+    idx.insert_u32(1u32, &narrow).unwrap();
+    idx.insert(2, &wide).unwrap();
+    assert_eq!(idx.query_candidates_u32(&narrow, 6), vec![(1, 6), (2, 6)]);
+    assert_eq!(idx.query_candidates(&wide, 6), vec![(1, 6), (2, 6)]);
+    assert_eq!(idx.query_by_id(1, 6), Some(vec![(1, 6), (2, 6)]));
+
+    let mut custom = LshIndex::with_mixer(params, 0, Summing);
+    custom.insert_u32(1u32, &narrow).unwrap();
+    assert_eq!(custom.query_candidates(&wide, 6), vec![(1, 6)]);
+}
+
+#[test]
+fn lsh_collision_histogram_over_queries() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let a: Vec<u64> = (0..8).collect();
+    let mut b = a.clone();
+    b[0] = 100; // shares 3 bands with `a`
+    let c: Vec<u64> = (50..58).collect();
+    idx.insert(1u32, &a).unwrap();
+    idx.insert(2, &b).unwrap();
+    idx.insert(3, &c).unwrap();
+
+    let hist = idx.collision_histogram(&[a.clone(), c.clone()]);
+    // a: (1, 4), (2, 3); c: (3, 4).
+    assert_eq!(hist, vec![0, 0, 0, 1, 2]);
+    assert_eq!(idx.collision_histogram(&[]), vec![0; 5]);
+}
+
+#[test]
+fn lsh_query_candidates_bands_scans_subset() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let a: Vec<u64> = (0..8).collect();
+    let mut b = a.clone();
+    b[0] = 100; // differs in band 0 only
+    idx.insert(1u32, &a).unwrap();
+    idx.insert(2, &b).unwrap();
+
+    assert_eq!(
+        idx.query_candidates_bands(&a, 0..2, 1),
+        vec![(1, 2), (2, 1)]
+    );
+    assert_eq!(
+        idx.query_candidates_bands(&a, 1..4, 3),
+        vec![(1, 3), (2, 3)]
+    );
+    assert_eq!(
+        idx.query_candidates_bands(&a, 0..4, 1),
+        idx.query_candidates(&a, 1)
+    );
+    assert!(idx.query_candidates_bands(&a, 2..2, 0).is_empty());
+    // Only the rows of bands 0..2 are needed.
+    assert_eq!(idx.query_candidates_bands(&a[..4], 0..2, 2), vec![(1, 2)]);
+}
+
+#[test]
+#[should_panic(expected = "out of bounds")]
+fn lsh_query_candidates_bands_rejects_bad_range() {
+    let idx: LshIndex = LshIndex::with_params(LshParams::new(4, 2).unwrap());
+    idx.query_candidates_bands(&[0; 8], 2..5, 1);
+}
+
+#[test]
+fn splitmix64_rng_matches_crate_seed_chain() {
+    use kira_cdh_compat_lsh::util::{SplitMix64Rng, mix_with_seed, splitmix64};
+    let mut rng = SplitMix64Rng::new(42);
+    let first = rng.next_u64();
+    assert_eq!(first, splitmix64(42));
+    assert_eq!(rng.next_u64(), splitmix64(first));
+    let mut again = SplitMix64Rng::new(42);
+    assert_eq!(again.next_u64(), first);
+
+    // The stream reproduces MinHash's per-hash seeds: one element's
+    // signature is its mix under each seed.
+    let mut seeds = SplitMix64Rng::new(7);
+    let expected: Vec<u64> = (0..16)
+        .map(|_| mix_with_seed(99, seeds.next_u64()))
+        .collect();
+    let mut mh = MinHash::new(16, 7);
+    mh.update(99);
+    assert_eq!(mh.finish(), expected);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn lsh_params_min_collisions_for_recall_matches_brute_force() {
+    let params = LshParams::new(6, 3).unwrap();
+    for &s in &[0.3, 0.6, 0.8, 0.95] {
+        let p: f64 = s * s * s;
+        // P[collisions >= t] by enumerating every band collision pattern.
+        let tail = |t: u32| -> f64 {
+            (0u32..1 << 6)
+                .filter(|mask| mask.count_ones() >= t)
+                .map(|mask| {
+                    let c = mask.count_ones() as i32;
+                    p.powi(c) * (1.0 - p).powi(6 - c)
+                })
+                .sum()
+        };
+        for &recall in &[0.5, 0.9, 0.99] {
+            let expected = (1..=6).rev().find(|&t| tail(t) >= recall).unwrap_or(0);
+            assert_eq!(
+                params.min_collisions_for_recall(s, recall),
+                expected as usize,
+                "s={s} recall={recall}"
+            );
+        }
+    }
+    assert_eq!(params.min_collisions_for_recall(1.0, 1.0), 6);
+    assert_eq!(params.min_collisions_for_recall(0.0, 0.5), 0);
+}
+
+#[test]
+fn compact_index_late_inserts_and_recompile() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let params = LshParams::new(8, 2).unwrap();
+    let sig = |id: u64| -> Vec<u64> { (0..16).map(|i| splitmix64(id % 5 * 16 + i)).collect() };
+    let mut full = LshIndex::with_params(params.clone());
+    let mut early = LshIndex::with_params(params);
+    for id in 0..20u32 {
+        full.insert(id, &sig(u64::from(id))).unwrap();
+        if id < 10 {
+            early.insert(id, &sig(u64::from(id))).unwrap();
+        }
+    }
+    let mut compact = early.compile();
+    compact.set_overflow_limit(100);
+    for id in 10..20u32 {
+        compact.insert(id, &sig(u64::from(id))).unwrap();
+    }
+    assert_eq!(compact.len(), 20);
+    assert_eq!(compact.overflow_len(), 10);
+    let probe = sig(3);
+    let expected = full.query_candidates(&probe, 1);
+    assert_eq!(compact.query_candidates(&probe, 1), expected);
+
+    compact.recompile();
+    assert_eq!(compact.overflow_len(), 0);
+    assert_eq!(compact.query_candidates(&probe, 1), expected);
+
+    // Exceeding the limit recompiles automatically.
+    compact.set_overflow_limit(1);
+    compact.insert(20, &sig(3)).unwrap();
+    compact.insert(21, &sig(3)).unwrap();
+    assert_eq!(compact.overflow_len(), 0);
+    assert_eq!(
+        compact.query_candidates(&probe, 8).len(),
+        expected.len() + 2
+    );
+    assert!(compact.insert(22, &probe[..4]).is_err());
+}
+
+#[test]
+fn jaccard_weighted_positions_generalizes_positional_estimate() {
+    use kira_cdh_compat_lsh::sketch::jaccard_weighted_positions;
+    let a = [1u64, 2, 3, 4];
+    let b = [1u64, 9, 3, 9];
+    assert_eq!(
+        jaccard_weighted_positions(&a, &b, &[1.0; 4]),
+        jaccard_from_signatures(&a, &b)
+    );
+    assert_eq!(
+        jaccard_weighted_positions(&a, &b, &[3.0, 1.0, 0.0, 0.0]),
+        0.75
+    );
+    assert_eq!(
+        jaccard_weighted_positions(&a, &a, &[0.5, 0.0, 2.0, 1.0]),
+        1.0
+    );
+}
+
+#[test]
+#[should_panic(expected = "one weight per signature position")]
+fn jaccard_weighted_positions_rejects_length_mismatch() {
+    kira_cdh_compat_lsh::sketch::jaccard_weighted_positions(&[1, 2], &[1, 2], &[1.0]);
+}
+
+#[test]
+fn lsh_count_candidates_matches_query_len() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let a: Vec<u64> = (0..8).collect();
+    for id in 0..5u32 {
+        let mut sig = a.clone();
+        sig[..2 * id as usize].iter_mut().for_each(|v| *v += 100);
+        idx.insert(id, &sig).unwrap();
+    }
+    for min in 0..=5 {
+        assert_eq!(
+            idx.count_candidates(&a, min),
+            idx.query_candidates(&a, min).len()
+        );
+    }
+    assert_eq!(idx.count_candidates(&a, 3), 2);
+}
+
+#[test]
+fn lsh_two_pass_query_matches_per_band_path_with_many_bands() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    // More bands than the inline bucket buffer holds.
+    let params = LshParams::new(100, 1).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let base: Vec<u64> = (0..100).map(splitmix64).collect();
+    for id in 0..30u32 {
+        let mut sig = base.clone();
+        sig.iter_mut()
+            .step_by(id as usize + 1)
+            .for_each(|v| *v += 1);
+        idx.insert(id, &sig).unwrap();
+    }
+    let detailed: Vec<(u32, u32)> = idx
+        .query_candidates_detailed(&base, 1)
+        .into_iter()
+        .map(|(id, bands)| (id, bands.len() as u32))
+        .collect();
+    assert_eq!(idx.query_candidates(&base, 1), detailed);
+    assert_eq!(detailed.len(), 29);
+}
+
+#[test]
+fn lsh_query_candidates_dense_matches_sparse() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let params = LshParams::new(8, 2).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    let base: Vec<u64> = (0..16).map(splitmix64).collect();
+    for id in 0..50u32 {
+        let mut sig = base.clone();
+        sig.iter_mut()
+            .skip(id as usize % 17)
+            .for_each(|v| *v ^= u64::from(id));
+        idx.insert(id, &sig).unwrap();
+    }
+    idx.insert(7, &base).unwrap(); // repeated ID still counts once per band
+    for min in [0, 1, 4, 8] {
+        assert_eq!(
+            idx.query_candidates_dense(&base, min, 50),
+            idx.query_candidates(&base, min)
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "0..n_ids")]
+fn lsh_query_candidates_dense_rejects_out_of_range_ids() {
+    let mut idx = LshIndex::with_params(LshParams::new(2, 2).unwrap());
+    let sig = [1u64, 2, 3, 4];
+    idx.insert(10u32, &sig).unwrap();
+    idx.query_candidates_dense(&sig, 1, 10);
+}
+
+#[test]
+fn lsh_params_as_map_key() {
+    use std::collections::HashMap;
+    let mut registry: HashMap<(LshParams, u64), &str> = HashMap::new();
+    registry.insert((LshParams::new(16, 8).unwrap(), 1), "a");
+    registry.insert((LshParams::new(8, 16).unwrap(), 1), "b");
+    assert_eq!(
+        LshParams::balanced(128).unwrap(),
+        LshParams::new(16, 8).unwrap()
+    );
+    assert_eq!(registry[&(LshParams::balanced(128).unwrap(), 1)], "a");
+    assert_eq!(registry.len(), 2);
+}
+
+#[test]
+fn kmv_update_weighted_estimates_weighted_jaccard() {
+    use kira_cdh_compat_lsh::util::splitmix64;
+    let mut one = KmvSketch::new(8);
+    let mut plain = KmvSketch::new(8);
+    one.update_weighted(42, 1);
+    one.update_weighted(7, 0);
+    plain.update(42);
+    assert_eq!(one.num_updates(), 1);
+    assert_eq!(one.finish(), plain.finish());
+
+    // Every element has multiplicity 1 in `a` and 3 in `b`: weighted
+    // Jaccard is 1/3 although the sets are equal.
+    let (mut a, mut b) = (KmvSketch::new(256), KmvSketch::new(256));
+    for x in 0..2000u64 {
+        a.update_weighted(splitmix64(x), 1);
+        b.update_weighted(splitmix64(x), 3);
+    }
+    assert_eq!(b.num_updates(), 6000);
+    let j = jaccard_kmv(&a.finish(), &b.finish(), 256);
+    assert!((j - 1.0 / 3.0).abs() < 0.1, "j={j}");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn band_entropy_flags_degenerate_bands() {
+    let params = LshParams::new(2, 1).unwrap();
+    let mut idx: LshIndex = LshIndex::with_params(params.clone());
+    // Band 0 always holds 7 (one bucket); band 1 is distinct per ID.
+    for id in 0..8u32 {
+        idx.insert(id, &[7, 100 + u64::from(id)]).unwrap();
+    }
+    let h = idx.band_entropy();
+    assert_eq!(h.len(), 2);
+    assert_eq!(h[0], 0.0);
+    assert!((h[1] - 3.0).abs() < 1e-12, "h={h:?}");
+
+    let empty: LshIndex = LshIndex::with_params(params);
+    assert_eq!(empty.band_entropy(), vec![0.0, 0.0]);
+}
+
+#[test]
+fn sketch_ext_matches_explicit_sketches() {
+    use kira_cdh_compat_lsh::sketch::SketchExt;
+    let hashes: Vec<u64> = (0..500u64)
+        .map(kira_cdh_compat_lsh::util::splitmix64)
+        .collect();
+
+    let mut mh = MinHash::new(64, 9);
+    mh.update_all(&hashes);
+    assert_eq!(hashes.iter().copied().minhash_sketch(64, 9), mh.finish());
+
+    let mut kmv = KmvSketch::new(32);
+    hashes.iter().for_each(|&h| kmv.update(h));
+    assert_eq!(hashes.clone().into_iter().kmv_sketch(32), kmv.finish());
+    assert_eq!((0..3u64).kmv_sketch(32).len(), 3);
+}
+
+#[test]
+fn lsh_query_kmers_round_trips_insert_kmers() {
+    use kira_cdh_compat_lsh::{errors::LshError, util::splitmix64};
+    let params = LshParams::new(8, 4).unwrap();
+    let mut idx = LshIndex::with_params(params);
+    idx.insert_kmers(1, (0..500).map(splitmix64), 32).unwrap();
+    idx.insert_kmers(2, (0..10).map(splitmix64), 32).unwrap();
+    idx.insert_kmers(3, (1000..1500).map(splitmix64), 32)
+        .unwrap();
+
+    assert_eq!(
+        idx.query_kmers((0..500).map(splitmix64), 32, 8).unwrap(),
+        vec![(1, 8)]
+    );
+    // Short inputs are padded the same way on both sides.
+    assert_eq!(
+        idx.query_kmers((0..10).rev().map(splitmix64), 32, 8)
+            .unwrap(),
+        vec![(2, 8)]
+    );
+    assert!(matches!(
+        idx.query_kmers((0..500).map(splitmix64), 16, 1),
+        Err(LshError::InvalidParams { sig_len: 16, .. })
+    ));
+}
+
+#[test]
+fn lsh_with_capacity_presizes_bands() {
+    let params = LshParams::new(4, 2).unwrap();
+    let mut sized: LshIndex = LshIndex::with_capacity(params.clone(), 1000);
+    let mut plain: LshIndex = LshIndex::with_params(params);
+    assert!(sized.is_empty());
+    assert!(sized.stats().estimated_bytes > plain.stats().estimated_bytes);
+
+    plain.reserve(1000);
+    for id in 0..1000u32 {
+        let sig: Vec<u64> = (0..8).map(|i| u64::from(id) * 8 + i).collect();
+        sized.insert(id, &sig).unwrap();
+        plain.insert(id, &sig).unwrap();
+    }
+    assert_eq!(plain.stats().buckets_per_band, vec![1000; 4]);
+    let q: Vec<u64> = (0..8).map(|i| 42 * 8 + i).collect();
+    assert_eq!(sized.query_candidates(&q, 1), plain.query_candidates(&q, 1));
+}